@@ -41,8 +41,22 @@ pub(crate) struct Config {
     #[arg(
         long,
         short,
-        help = "Number of hours to wait before expiring a non-refreshed registration",
+        help = "Number of hours to wait before expiring a non-refreshed registration. Clients may request less.",
         default_value_t = 12
     )]
     pub ttl: u16,
+
+    #[arg(
+        long,
+        help = "Shortest registration lifetime clients may request, in seconds",
+        default_value_t = 60
+    )]
+    pub min_ttl: u32,
+
+    #[arg(
+        long,
+        help = "Maximum number of registrations returned by a single discovery request",
+        default_value_t = 100
+    )]
+    pub page_size: u32,
 }
@@ -72,7 +72,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
                                 .to_str()
                                 .expect("Expected a valid database path."),
                         )
+                        .min_lifetime(TimeDelta::seconds(config.min_ttl.into()))
                         .max_lifetime(TimeDelta::hours(config.ttl.into()))
+                        .max_page_size(config.page_size)
                         .build()?,
                 ),
                 ping: ping::Behaviour::default(),
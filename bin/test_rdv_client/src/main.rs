@@ -41,6 +41,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         .discoverability(Discoverability::Namespace)
                         .group("subgroup")
                         .build()?,
+                    key.clone(),
                 ),
             })
         })?
@@ -53,7 +54,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         match swarm.select_next_some().await {
             SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == rendezvous_id => {
                 swarm.behaviour_mut().rendezvous.register(&peer_id).unwrap();
-                swarm.behaviour_mut().rendezvous.discover(&peer_id, None);
+                swarm.behaviour_mut().rendezvous.discover_all(&peer_id, None, None);
             }
             e => {
                 println!("{e:?}");
@@ -0,0 +1,332 @@
+use std::{
+    fmt::Debug,
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use asynchronous_codec::{BytesMut, Decoder, Encoder, Framed};
+use libp2p::futures::{
+    AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, Sink, Stream,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use unsigned_varint::{aio, decode, encode};
+
+use crate::{
+    error::{CResult, Error},
+    stream::InterplexStream,
+};
+
+/// Largest frame accepted by default, in bytes
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Serialization format of framed messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    Cbor,
+
+    #[default]
+    MessagePack,
+
+    Json,
+}
+
+impl Format {
+    pub fn encode<T: Serialize>(&self, value: &T) -> CResult<Vec<u8>> {
+        match self {
+            Format::Cbor => serde_cbor::to_vec(value).map_err(Error::encoding),
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(Error::encoding),
+            Format::Json => serde_json::to_vec(value).map_err(Error::encoding),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> CResult<T> {
+        match self {
+            Format::Cbor => serde_cbor::from_slice(data).map_err(Error::encoding),
+            Format::MessagePack => rmp_serde::from_slice(data).map_err(Error::encoding),
+            Format::Json => serde_json::from_slice(data).map_err(Error::encoding),
+        }
+    }
+}
+
+/// Writes a single frame, prefixed with its length as an unsigned varint
+pub(crate) async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> CResult<()> {
+    let mut prefix = encode::usize_buffer();
+    writer.write_all(encode::usize(data.len(), &mut prefix)).await?;
+    writer.write_all(data).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads a single length-prefixed frame, rejecting frames larger than `max_size`
+pub(crate) async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> CResult<Vec<u8>> {
+    let size = aio::read_usize(&mut *reader).await.map_err(Error::io)?;
+    if size > max_size {
+        return Err(Error::FrameTooLarge {
+            size,
+            max: max_size,
+        });
+    }
+
+    let mut buffer = vec![0u8; size];
+    reader.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
+
+/// Codec for varint length-prefixed frames, each holding one serialized message
+pub(crate) struct FrameCodec<T> {
+    format: Format,
+    max_size: usize,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize> Encoder for FrameCodec<T> {
+    type Item<'a> = T;
+    type Error = Error;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let data = self.format.encode(&item)?;
+        if data.len() > self.max_size {
+            return Err(Error::FrameTooLarge {
+                size: data.len(),
+                max: self.max_size,
+            });
+        }
+
+        let mut prefix = encode::usize_buffer();
+        dst.reserve(prefix.len() + data.len());
+        dst.extend_from_slice(encode::usize(data.len(), &mut prefix));
+        dst.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+impl<T: DeserializeOwned> Decoder for FrameCodec<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let (size, remaining) = match decode::usize(src) {
+            Ok(decoded) => decoded,
+            Err(decode::Error::Insufficient) => return Ok(None),
+            Err(e) => return Err(Error::encoding(e)),
+        };
+
+        if size > self.max_size {
+            return Err(Error::FrameTooLarge {
+                size,
+                max: self.max_size,
+            });
+        }
+
+        let prefix = src.len() - remaining.len();
+        if remaining.len() < size {
+            src.reserve(prefix + size - src.len());
+            return Ok(None);
+        }
+
+        let _ = src.split_to(prefix);
+        let frame = src.split_to(size);
+        self.format.decode(&frame).map(Some)
+    }
+}
+
+/// Typed messages sent over an [`InterplexStream`].
+///
+/// Each message is serialized with the chosen [`Format`] and prefixed with its length as an
+/// unsigned varint. Messages are sent through [`Sink`] and received through [`Stream`]. A message
+/// that fails to decode yields an error without closing the stream, but a frame larger than the
+/// maximum frame size yields [`Error::FrameTooLarge`], after which the stream should be dropped.
+pub struct FramedStream<T> {
+    inner: Framed<InterplexStream, FrameCodec<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned> FramedStream<T> {
+    pub fn new(stream: InterplexStream, format: Format) -> Self {
+        Self {
+            inner: Framed::new(
+                stream,
+                FrameCodec {
+                    format,
+                    max_size: DEFAULT_MAX_FRAME_SIZE,
+                    _message: PhantomData,
+                },
+            ),
+        }
+    }
+
+    /// Sets the largest frame that may be sent or received, in bytes
+    pub fn with_max_frame_size(mut self, max_size: usize) -> Self {
+        self.inner.codec_mut().max_size = max_size;
+        self
+    }
+
+    pub fn format(&self) -> Format {
+        self.inner.codec().format
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.inner.codec().max_size
+    }
+
+    pub fn get_ref(&self) -> &InterplexStream {
+        &self.inner
+    }
+
+    /// Returns the underlying stream. Any buffered data that has not been read is lost.
+    pub fn into_inner(self) -> InterplexStream {
+        self.inner.into_inner()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Debug for FramedStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FramedStream")
+            .field("stream", self.get_ref())
+            .field("format", &self.format())
+            .field("max_frame_size", &self.max_frame_size())
+            .finish()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Stream for FramedStream<T> {
+    type Item = CResult<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Sink<T> for FramedStream<T> {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        Pin::new(&mut self.inner).start_send(item)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+impl InterplexStream {
+    /// Wraps this stream to send and receive typed, length-prefixed messages
+    pub fn framed<T: Serialize + DeserializeOwned>(self, format: Format) -> FramedStream<T> {
+        FramedStream::new(self, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::futures::io::Cursor;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        id: u32,
+        text: String,
+        tags: Vec<String>,
+    }
+
+    fn message() -> Message {
+        Message {
+            id: 7,
+            text: String::from("hello"),
+            tags: vec![String::from("a"), String::from("b")],
+        }
+    }
+
+    fn codec(format: Format, max_size: usize) -> FrameCodec<Message> {
+        FrameCodec {
+            format,
+            max_size,
+            _message: PhantomData,
+        }
+    }
+
+    /// Varint length prefix announcing a frame of `size` bytes
+    fn prefix(size: usize) -> Vec<u8> {
+        let mut buffer = encode::usize_buffer();
+        encode::usize(size, &mut buffer).to_vec()
+    }
+
+    #[test]
+    fn codec_round_trips_each_format() {
+        for format in [Format::Cbor, Format::MessagePack, Format::Json] {
+            let mut codec = codec(format, DEFAULT_MAX_FRAME_SIZE);
+            let mut buffer = BytesMut::new();
+            codec.encode(message(), &mut buffer).unwrap();
+            codec.encode(message(), &mut buffer).unwrap();
+
+            // A partial frame waits for more data
+            let mut partial = BytesMut::from(&buffer[..buffer.len() / 2 - 1]);
+            assert_eq!(codec.decode(&mut partial).unwrap(), None, "{format:?}");
+
+            assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message()), "{format:?}");
+            assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message()), "{format:?}");
+            assert!(buffer.is_empty(), "{format:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip_each_format() {
+        for format in [Format::Cbor, Format::MessagePack, Format::Json] {
+            let mut stream = Cursor::new(Vec::new());
+            write_frame(&mut stream, &format.encode(&message()).unwrap())
+                .await
+                .unwrap();
+
+            stream.set_position(0);
+            let frame = read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE).await.unwrap();
+            assert_eq!(format.decode::<Message>(&frame).unwrap(), message(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn codec_rejects_oversized_frames_without_allocating() {
+        let mut codec = codec(Format::default(), 1024);
+        let mut buffer = BytesMut::from(&prefix(1 << 40)[..]);
+        buffer.extend_from_slice(b"data");
+        let capacity = buffer.capacity();
+
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(Error::FrameTooLarge { size, max: 1024 }) if size == 1 << 40
+        ));
+        assert_eq!(buffer.capacity(), capacity);
+
+        let oversized = Message {
+            text: "x".repeat(2048),
+            ..message()
+        };
+        let mut buffer = BytesMut::new();
+        assert!(matches!(
+            codec.encode(oversized, &mut buffer),
+            Err(Error::FrameTooLarge { max: 1024, .. })
+        ));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_frame_rejects_oversized_frames_without_allocating() {
+        // Allocating a buffer of the announced size would abort the test
+        let mut stream = Cursor::new(prefix(1 << 60));
+
+        assert!(matches!(
+            read_frame(&mut stream, 1024).await,
+            Err(Error::FrameTooLarge { size, max: 1024 }) if size == 1 << 60
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+use std::time::Duration;
+
+use interplex_common::rendezvous::query::Query;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How the node reconnects to a rendezvous point after losing its connection.
+///
+/// Each failed attempt multiplies the delay before the next one, up to `max_delay`. Every delay is
+/// then randomized by up to `jitter` (as a fraction of the delay) so that many nodes losing the
+/// same rendezvous point don't all redial it at once.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReconnectPolicy {
+    /// Delay before the first redial
+    pub initial_delay: Duration,
+
+    /// Longest delay between two redials
+    pub max_delay: Duration,
+
+    /// Factor the delay grows by after each failed redial
+    pub multiplier: f64,
+
+    /// Fraction of each delay that is randomized, between 0 and 1
+    pub jitter: f64,
+
+    /// Number of consecutive failed redials before giving up, or `None` to retry forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(300),
+            multiplier: 2.0,
+            jitter: 0.25,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// A policy that never redials
+    pub fn disabled() -> Self {
+        Self {
+            max_attempts: Some(0),
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the given redial, counting from zero
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let max = self.max_delay.as_secs_f64();
+        let base = self.initial_delay.as_secs_f64()
+            * self.multiplier.max(1.0).powi(attempt.min(i32::MAX as u32) as i32);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+
+        Duration::from_secs_f64((base.min(max) * factor).min(max))
+    }
+}
+
+/// Protocol used to publish and receive topic messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PubsubEngine {
+    /// Messages are gossiped through a mesh of peers, which scales to large groups
+    #[default]
+    Gossipsub,
+
+    /// Messages are flooded to every known peer, which is simpler but only suits small groups
+    Floodsub,
+}
+
+/// Which nodes share a topic name
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TopicScope {
+    /// Nodes in the same namespace, as `interplex/<namespace>/n/<topic>`
+    #[default]
+    Namespace,
+
+    /// Nodes in the same namespace and group, as `interplex/<namespace>/g/<group>/<topic>`
+    Group,
+
+    /// Every node, regardless of namespace or group, as `global/<topic>`
+    Global,
+}
+
+/// How gossipsub identifies messages, which decides what it discards as duplicates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageIdMode {
+    /// Messages are identified by their source and sequence number
+    #[default]
+    SourceAndSequence,
+
+    /// Messages are identified by a hash of their source and data, so identical messages of the
+    /// same publisher are delivered once. This includes data a publisher sends again on purpose,
+    /// such as a heartbeat, within [`GossipsubSettings::duplicate_cache_time`] of the last one. With
+    /// [`ValidationMode::Anonymous`], messages have no source, so identical messages are delivered
+    /// once whoever published them.
+    Content,
+}
+
+/// How strictly gossipsub checks the signature fields of received messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ValidationMode {
+    /// Messages must be signed, and carry a source and sequence number
+    #[default]
+    Strict,
+
+    /// Signatures and sequence numbers are verified only when present
+    Permissive,
+
+    /// Messages must not carry a signature, source or sequence number. Messages are published
+    /// without them as well, so receivers cannot tell who published a message.
+    Anonymous,
+
+    /// Messages are not checked at all
+    None,
+}
+
+/// Gossipsub settings. The defaults match those of libp2p.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipsubSettings {
+    /// Number of peers each topic mesh aims for
+    pub mesh_n: usize,
+
+    /// Fewest peers in a topic mesh before more are added
+    pub mesh_n_low: usize,
+
+    /// Most peers in a topic mesh before some are removed
+    pub mesh_n_high: usize,
+
+    /// Fewest outbound peers kept in a topic mesh
+    pub mesh_outbound_min: usize,
+
+    /// Number of peers outside the mesh that gossip is sent to
+    pub gossip_lazy: usize,
+
+    /// Time between heartbeats, which maintain the mesh and send gossip
+    pub heartbeat_interval: Duration,
+
+    /// Number of heartbeats that recent messages are cached for
+    pub history_length: usize,
+
+    /// Number of heartbeats that cached messages are gossiped for
+    pub history_gossip: usize,
+
+    /// Largest message that may be sent or received, in bytes
+    pub max_transmit_size: usize,
+
+    /// How long message IDs are remembered to discard duplicates
+    pub duplicate_cache_time: Duration,
+
+    pub message_id: MessageIdMode,
+    pub validation: ValidationMode,
+}
+
+impl Default for GossipsubSettings {
+    fn default() -> Self {
+        Self {
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            mesh_outbound_min: 2,
+            gossip_lazy: 6,
+            heartbeat_interval: Duration::from_secs(1),
+            history_length: 5,
+            history_gossip: 3,
+            max_transmit_size: 65536,
+            duplicate_cache_time: Duration::from_secs(60),
+            message_id: MessageIdMode::default(),
+            validation: ValidationMode::default(),
+        }
+    }
+}
+
+/// How many recent messages are kept for each subscribed topic, so that peers subscribing later
+/// can pull them (see [`crate::InterplexNode::pull_history`]). Messages are dropped once they
+/// exceed either limit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryPolicy {
+    /// Most messages kept per topic
+    pub max_messages: usize,
+
+    /// Longest time a message is kept, or `None` to keep messages until they are pushed out
+    pub max_age: Option<Duration>,
+}
+
+impl Default for HistoryPolicy {
+    fn default() -> Self {
+        Self {
+            max_messages: 128,
+            max_age: None,
+        }
+    }
+}
+
+/// Network settings of a node, set through [`crate::NodeBuilder`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub reconnect: ReconnectPolicy,
+    pub pubsub: PubsubEngine,
+
+    /// Scope of topics given without an explicit one
+    pub topic_scope: TopicScope,
+    pub gossipsub: GossipsubSettings,
+
+    /// How often rendezvous points are asked for peers, or `None` to only discover on request
+    pub discovery_interval: Option<Duration>,
+
+    /// Group that discovery is limited to, or `None` for the whole namespace
+    pub discovery_group: Option<String>,
+
+    /// Query that discovered peers must match, evaluated by the rendezvous points
+    pub discovery_query: Option<Query>,
+
+    /// Lifetime requested for registrations with rendezvous points, or `None` for the longest
+    /// they allow. Rendezvous points may grant a different one.
+    pub registration_ttl: Option<Duration>,
+
+    /// Most discovered peers that are connected to for pubsub, or 0 to not connect to any
+    pub peer_budget: usize,
+
+    /// Recent messages kept for each subscribed topic, or `None` to keep none
+    pub topic_history: Option<HistoryPolicy>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: ReconnectPolicy::default(),
+            pubsub: PubsubEngine::default(),
+            topic_scope: TopicScope::default(),
+            gossipsub: GossipsubSettings::default(),
+            discovery_interval: None,
+            discovery_group: None,
+            discovery_query: None,
+            registration_ttl: None,
+            peer_budget: 32,
+            topic_history: None,
+        }
+    }
+}
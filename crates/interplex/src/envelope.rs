@@ -0,0 +1,258 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use interplex_common::identification::NodeIdentifier;
+use libp2p::{
+    bytes::Bytes,
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{CResult, Error};
+
+/// Prefix of every signed payload, so that envelope signatures can't be passed off as anything else
+const SIGNING_DOMAIN: &str = "interplex/envelope";
+
+/// The identity a message was published under, as signed by its sender.
+///
+/// This is a compact form of the sender's [`NodeIdentifier`], without its metadata. The full
+/// identifier of a discovered peer is available through its discovery events.
+///
+/// The signature only proves the peer ID: the other fields are stated by the sender. Received
+/// messages are rejected if their sender claims another namespace than the one it was discovered
+/// in, or a namespace or group outside the scope of the topic. The alias and group of discovered
+/// senders are replaced by those they registered with, but those of senders that weren't
+/// discovered, such as nodes of other namespaces on global topics, are as claimed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderIdentity {
+    pub peer_id: PeerId,
+    pub namespace: String,
+
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+impl SenderIdentity {
+    pub fn name(&self) -> String {
+        self.alias.clone().unwrap_or(self.peer_id.to_string())
+    }
+
+    pub fn group(&self) -> String {
+        self.group.clone().unwrap_or(String::from("default"))
+    }
+}
+
+impl From<&NodeIdentifier> for SenderIdentity {
+    fn from(value: &NodeIdentifier) -> Self {
+        Self {
+            peer_id: value.peer_id,
+            namespace: value.namespace.clone(),
+            alias: value.alias.clone(),
+            group: value.group.clone(),
+        }
+    }
+}
+
+/// Wrapper around every published message
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    seqno: u64,
+
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+
+    /// Missing when publishing anonymously
+    signature: Option<EnvelopeSignature>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EnvelopeSignature {
+    /// Protobuf encoding of the sender's public key
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+
+    identity: SenderIdentity,
+
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+/// Identifies a message to discard duplicates: by its signer and sequence number, or by a hash of
+/// its contents if it was published anonymously
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum MessageId {
+    Signed(PeerId, u64),
+    Anonymous([u8; 16]),
+}
+
+/// The contents of an envelope that passed verification
+pub(crate) struct OpenedEnvelope {
+    pub id: MessageId,
+    pub seqno: u64,
+    pub data: Bytes,
+    pub sender: Option<SenderIdentity>,
+}
+
+impl Envelope {
+    /// Wraps a message published on `topic` (its name on the network), signing it if a keypair
+    /// and identity are given
+    pub fn seal(
+        topic: &str,
+        seqno: u64,
+        data: Bytes,
+        signer: Option<(&Keypair, SenderIdentity)>,
+    ) -> CResult<Self> {
+        let signature = match signer {
+            Some((keypair, identity)) => {
+                let payload = signing_payload(topic, seqno, &identity, &data)?;
+                Some(EnvelopeSignature {
+                    key: keypair.public().encode_protobuf(),
+                    signature: keypair.sign(&payload).map_err(Error::encoding)?,
+                    identity,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            seqno,
+            data: data.to_vec(),
+            signature,
+        })
+    }
+
+    pub fn encode(&self) -> CResult<Bytes> {
+        rmp_serde::to_vec(self)
+            .map(Bytes::from)
+            .map_err(Error::encoding)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        rmp_serde::from_slice(data).map_err(|error| format!("Malformed envelope: {error}"))
+    }
+
+    /// The message wrapped by the envelope, without verifying it
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Identifies the envelope as published on `topic` (its name on the network)
+    pub fn id(&self, topic: &str) -> MessageId {
+        match &self.signature {
+            Some(signed) => MessageId::Signed(signed.identity.peer_id, self.seqno),
+            None => MessageId::Anonymous(content_digest(
+                topic,
+                &[&self.seqno.to_be_bytes(), &self.data],
+            )),
+        }
+    }
+
+    /// Verifies that the envelope was signed by the identity it carries, for the topic it was
+    /// received on. Unsigned envelopes are only accepted if `require_signature` is false.
+    pub fn open(self, topic: &str, require_signature: bool) -> Result<OpenedEnvelope, String> {
+        let id = self.id(topic);
+        let sender = match self.signature {
+            Some(signed) => {
+                let key = PublicKey::try_decode_protobuf(&signed.key)
+                    .map_err(|error| format!("Invalid signing key: {error}"))?;
+                if key.to_peer_id() != signed.identity.peer_id {
+                    return Err(format!(
+                        "Signing key does not belong to {}",
+                        signed.identity.peer_id
+                    ));
+                }
+
+                let payload = signing_payload(topic, self.seqno, &signed.identity, &self.data)
+                    .map_err(|error| error.to_string())?;
+                if !key.verify(&payload, &signed.signature) {
+                    return Err(String::from("Invalid signature"));
+                }
+
+                Some(signed.identity)
+            }
+            None if require_signature => return Err(String::from("Envelope is not signed")),
+            None => None,
+        };
+
+        Ok(OpenedEnvelope {
+            id,
+            seqno: self.seqno,
+            data: self.data.into(),
+            sender,
+        })
+    }
+}
+
+/// Truncated SHA-256 of a message published on `topic`. Unlike `DefaultHasher`, the digest is the
+/// same across builds and toolchains, so every node computes the same ID for a message.
+pub(crate) fn content_digest(topic: &str, parts: &[&[u8]]) -> [u8; 16] {
+    let mut hasher = Sha256::new();
+    hasher.update((topic.len() as u64).to_be_bytes());
+    hasher.update(topic);
+    for part in parts {
+        hasher.update(part);
+    }
+
+    let mut digest = [0; 16];
+    digest.copy_from_slice(&hasher.finalize()[..16]);
+    digest
+}
+
+/// Bytes covered by an envelope's signature. The topic is included so that a signed message can't
+/// be replayed on another topic.
+fn signing_payload(
+    topic: &str,
+    seqno: u64,
+    identity: &SenderIdentity,
+    data: &[u8],
+) -> CResult<Vec<u8>> {
+    rmp_serde::to_vec(&(
+        SIGNING_DOMAIN,
+        topic,
+        seqno,
+        identity,
+        serde_bytes::Bytes::new(data),
+    ))
+    .map_err(Error::encoding)
+}
+
+/// First sequence number of a node, taken from the clock so that it keeps increasing across
+/// restarts
+pub(crate) fn initial_seqno() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_digest_is_stable() {
+        // SHA-256 of the big-endian topic length, the topic and the data, truncated to 16 bytes
+        assert_eq!(
+            content_digest("topic", &[b"hello"]),
+            [
+                0x46, 0x52, 0x01, 0x40, 0x43, 0x50, 0x30, 0xb9, 0x66, 0xb7, 0xf2, 0xdf, 0xb7, 0x8f,
+                0xe1, 0x01
+            ]
+        );
+    }
+
+    #[test]
+    fn anonymous_ids_depend_on_topic_and_contents() {
+        let envelope = Envelope::seal("topic", 1, Bytes::from_static(b"hello"), None).unwrap();
+        let same = Envelope::seal("topic", 1, Bytes::from_static(b"hello"), None).unwrap();
+        let other = Envelope::seal("topic", 1, Bytes::from_static(b"world"), None).unwrap();
+
+        assert_eq!(envelope.id("topic"), same.id("topic"));
+        assert_ne!(envelope.id("topic"), envelope.id("other"));
+        assert_ne!(envelope.id("topic"), other.id("topic"));
+    }
+}
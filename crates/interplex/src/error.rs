@@ -6,6 +6,8 @@ use libp2p_stream::OpenStreamError;
 use thiserror::Error as ErrorDe;
 use uuid::Uuid;
 
+use crate::rpc::RemoteError;
+
 #[allow(dead_code)]
 #[derive(Clone, Debug, ErrorDe)]
 pub enum Error {
@@ -18,28 +20,6 @@ pub enum Error {
     #[error("Unknown stream ID: {id}")]
     UnknownStream { id: Uuid },
 
-    #[error("Failed to close stream {id} to peer {peer}: {reason}")]
-    CloseStream {
-        id: Uuid,
-        peer: PeerId,
-        reason: String,
-    },
-
-    #[error("Failed to read {buffer_size} bytes from stream {id} and peer {peer}: {reason}")]
-    ReadStream {
-        id: Uuid,
-        peer: PeerId,
-        buffer_size: usize,
-        reason: String,
-    },
-
-    #[error("Failed to write data to stream {id} and peer {peer}: {reason}")]
-    WriteStream {
-        id: Uuid,
-        peer: PeerId,
-        reason: String,
-    },
-
     #[error("A connection error occurred while {context}: {reason}")]
     ConnectionError { context: String, reason: String },
 
@@ -56,7 +36,44 @@ pub enum Error {
     },
 
     #[error("The network is not currently running.")]
-    NetworkOffline
+    NetworkOffline,
+
+    #[error("The network is already running.")]
+    NetworkRunning,
+
+    #[error("The network is shutting down.")]
+    NetworkStopping,
+
+    #[error("Received an unexpected response from the network: {0}")]
+    UnexpectedResponse(String),
+
+    #[error("An IO error occurred: {0}")]
+    Io(String),
+
+    #[error("Frame of {size} bytes exceeds the maximum frame size of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+
+    #[error("Invalid stream protocol (must start with /): {0}")]
+    InvalidProtocol(String),
+
+    #[error("Stream protocol {0} is already being accepted")]
+    ProtocolRegistered(String),
+
+    #[error("Request to {peer} on {protocol} timed out")]
+    RequestTimeout { peer: PeerId, protocol: String },
+
+    #[error("Peer {peer} failed to handle request: {error}")]
+    Remote { peer: PeerId, error: RemoteError },
+
+    #[error("Failed to publish to topic {topic}: {reason}")]
+    Publish { topic: String, reason: String },
+
+    #[error("Received a message from {peer} on topic {topic} that could not be decoded: {reason}")]
+    InvalidMessage {
+        topic: String,
+        peer: PeerId,
+        reason: String,
+    }
 }
 
 #[allow(dead_code)]
@@ -68,40 +85,10 @@ impl Error {
         }
     }
 
-    pub fn close_stream(stream_id: Uuid, peer_id: impl Into<PeerId>, error: impl Debug) -> Self {
-        Error::CloseStream {
-            id: stream_id,
-            peer: peer_id.into(),
-            reason: format!("{error:?}"),
-        }
-    }
-
     pub fn unknown_stream(id: Uuid) -> Self {
         Error::UnknownStream { id }
     }
 
-    pub fn read_stream(
-        stream_id: Uuid,
-        peer_id: impl Into<PeerId>,
-        bufsize: usize,
-        error: impl Debug,
-    ) -> Self {
-        Error::ReadStream {
-            id: stream_id,
-            peer: peer_id.into(),
-            buffer_size: bufsize,
-            reason: format!("{error:?}"),
-        }
-    }
-
-    pub fn write_stream(stream_id: Uuid, peer_id: impl Into<PeerId>, error: impl Debug) -> Self {
-        Error::WriteStream {
-            id: stream_id,
-            peer: peer_id.into(),
-            reason: format!("{error:?}"),
-        }
-    }
-
     pub fn connection(context: impl Into<String>, error: impl Debug) -> Self {
         Error::ConnectionError {
             context: context.into(),
@@ -113,6 +100,21 @@ impl Error {
         Error::DataEncoding(format!("{error:?}"))
     }
 
+    pub fn unexpected_response(response: impl Debug) -> Self {
+        Error::UnexpectedResponse(format!("{response:?}"))
+    }
+
+    pub fn io(error: impl Debug) -> Self {
+        Error::Io(format!("{error:?}"))
+    }
+
+    pub fn publish(topic: impl Into<String>, error: impl Debug) -> Self {
+        Error::Publish {
+            topic: topic.into(),
+            reason: format!("{error:?}"),
+        }
+    }
+
     pub fn build_node(reason: impl Into<String>) -> Self {
         Error::BuildNode(reason.into())
     }
@@ -122,6 +124,12 @@ impl Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::io(value)
+    }
+}
+
 impl From<InterplexError> for Error {
     fn from(value: InterplexError) -> Self {
         Error::Internal(value)
@@ -0,0 +1,280 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use libp2p::{
+    bytes::Bytes,
+    futures::{AsyncWriteExt as _, StreamExt as _},
+    PeerId, Stream, StreamProtocol,
+};
+use libp2p_stream::IncomingStreams;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use tokio::{
+    sync::Mutex,
+    task::JoinSet,
+    time::{timeout, Instant},
+};
+
+use crate::{
+    codec::{read_frame, write_frame, Format},
+    config::HistoryPolicy,
+    envelope::MessageId,
+    error::{CResult, Error},
+    ipc::{Command, CommandResponse},
+    pubsub::TopicName,
+    rpc::DEFAULT_REQUEST_TIMEOUT,
+    InterplexNode,
+};
+
+/// Protocol used to pull the recent messages of a topic from a peer
+pub const TOPIC_HISTORY_PROTOCOL: StreamProtocol = StreamProtocol::new("/interplex/topic-history");
+
+/// Serialization format of history requests and responses
+pub(crate) const HISTORY_FORMAT: Format = Format::MessagePack;
+
+/// Largest history request accepted, in bytes. Requests only carry a topic name.
+const MAX_HISTORY_REQUEST: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct HistoryRequest {
+    /// Name of the topic on the network
+    pub topic: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct HistoryResponse {
+    /// The recent messages, oldest first
+    pub messages: Vec<HistoryMessage>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct HistoryMessage {
+    /// Time since the message was first received, in milliseconds
+    pub age: u64,
+
+    /// Envelope the message was received in
+    pub envelope: ByteBuf,
+}
+
+struct HistoryEntry {
+    id: MessageId,
+    received: Instant,
+
+    /// Age of the message when it was received, which is only non-zero for pulled messages
+    age: Duration,
+    envelope: Bytes,
+}
+
+impl HistoryEntry {
+    fn age(&self) -> Duration {
+        self.age + self.received.elapsed()
+    }
+}
+
+/// Recent messages of a single topic, kept as the envelopes they were received in so that peers
+/// pulling them can verify them
+#[derive(Default)]
+pub(crate) struct TopicHistory {
+    entries: VecDeque<HistoryEntry>,
+    ids: HashSet<MessageId>,
+}
+
+impl TopicHistory {
+    /// Adds a message that is already `age` old (for messages pulled from peers), dropping the
+    /// oldest ones that no longer fit the policy. Returns false if the message was already kept,
+    /// or is too old to be kept.
+    pub fn insert(
+        &mut self,
+        id: MessageId,
+        envelope: Bytes,
+        age: Duration,
+        policy: &HistoryPolicy,
+    ) -> bool {
+        if policy.max_age.is_some_and(|max_age| age > max_age) || !self.ids.insert(id) {
+            return false;
+        }
+
+        self.entries.push_back(HistoryEntry {
+            id,
+            received: Instant::now(),
+            age,
+            envelope,
+        });
+        self.prune(policy);
+        true
+    }
+
+    /// Envelopes of the kept messages along with their age, oldest first
+    pub fn envelopes(&mut self, policy: &HistoryPolicy) -> Vec<(Duration, Bytes)> {
+        self.prune(policy);
+        self.entries
+            .iter()
+            .map(|entry| (entry.age(), entry.envelope.clone()))
+            .collect()
+    }
+
+    fn prune(&mut self, policy: &HistoryPolicy) {
+        // Pulled messages may be older than ones received before them, so every entry is checked
+        if let Some(max_age) = policy.max_age {
+            let ids = &mut self.ids;
+            self.entries.retain(|entry| {
+                let keep = entry.age() <= max_age;
+                if !keep {
+                    ids.remove(&entry.id);
+                }
+                keep
+            });
+        }
+
+        while self.entries.len() > policy.max_messages {
+            if let Some(entry) = self.entries.pop_front() {
+                self.ids.remove(&entry.id);
+            }
+        }
+    }
+}
+
+/// Answers history requests with the history of subscribed topics, until the streams end along
+/// with the swarm. Topics that aren't subscribed have no history.
+pub(crate) fn serve_history(
+    mut incoming: IncomingStreams,
+    history: Arc<Mutex<HashMap<String, TopicHistory>>>,
+    policy: HistoryPolicy,
+) {
+    tokio::spawn(async move {
+        let mut answering = JoinSet::new();
+        while let Some((peer, stream)) = incoming.next().await {
+            while answering.try_join_next().is_some() {}
+            let history = history.clone();
+            let policy = policy.clone();
+            answering.spawn(async move {
+                if let Err(error) = answer_history(stream, &history, &policy).await {
+                    tracing::debug!(%peer, "Failed to serve topic history: {error}");
+                }
+            });
+        }
+    });
+}
+
+async fn answer_history(
+    mut stream: Stream,
+    history: &Mutex<HashMap<String, TopicHistory>>,
+    policy: &HistoryPolicy,
+) -> CResult<()> {
+    let frame = timeout(
+        DEFAULT_REQUEST_TIMEOUT,
+        read_frame(&mut stream, MAX_HISTORY_REQUEST),
+    )
+    .await
+    .map_err(Error::io)??;
+    let request: HistoryRequest = HISTORY_FORMAT.decode(&frame)?;
+
+    let messages = history
+        .lock()
+        .await
+        .get_mut(&request.topic)
+        .map(|history| history.envelopes(policy))
+        .unwrap_or_default();
+    let response = HistoryResponse {
+        messages: messages
+            .into_iter()
+            .map(|(age, envelope)| HistoryMessage {
+                age: age.as_millis().try_into().unwrap_or(u64::MAX),
+                envelope: envelope.to_vec().into(),
+            })
+            .collect(),
+    };
+
+    write_frame(&mut stream, &HISTORY_FORMAT.encode(&response)?).await?;
+    stream.close().await?;
+    Ok(())
+}
+
+impl InterplexNode {
+    /// Pulls the recent messages of a subscribed topic from a peer that keeps its history (see
+    /// [`crate::NodeBuilder::topic_history`]), and delivers them like live messages, oldest first.
+    ///
+    /// Messages are verified like live ones. If this node keeps history as well, messages it
+    /// already received are skipped, so pulling from several peers delivers each message once.
+    /// Messages keep the age they had on the peer, and those older than this node's
+    /// [`HistoryPolicy::max_age`] are skipped too. Returns the number of messages delivered.
+    pub async fn pull_history(&self, topic: impl Into<TopicName>, peer: PeerId) -> CResult<usize> {
+        match self
+            .network
+            .command(Command::PullHistory {
+                topic: topic.into(),
+                peer,
+            })
+            .await?
+        {
+            CommandResponse::PullHistory(delivered) => Ok(delivered),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(seqno: u64) -> MessageId {
+        MessageId::Signed(PeerId::random(), seqno)
+    }
+
+    fn envelope(seqno: u64) -> Bytes {
+        Bytes::from(seqno.to_be_bytes().to_vec())
+    }
+
+    #[test]
+    fn keeps_the_latest_messages_up_to_capacity() {
+        let policy = HistoryPolicy {
+            max_messages: 3,
+            max_age: None,
+        };
+        let mut history = TopicHistory::default();
+        let ids: Vec<_> = (0..5).map(id).collect();
+        for (seqno, id) in ids.iter().enumerate() {
+            assert!(history.insert(*id, envelope(seqno as u64), Duration::ZERO, &policy));
+        }
+
+        let kept: Vec<_> = history
+            .envelopes(&policy)
+            .into_iter()
+            .map(|(_, envelope)| envelope)
+            .collect();
+        assert_eq!(kept, vec![envelope(2), envelope(3), envelope(4)]);
+
+        // Kept messages are duplicates, evicted ones may be kept again
+        assert!(!history.insert(ids[4], envelope(4), Duration::ZERO, &policy));
+        assert!(history.insert(ids[0], envelope(0), Duration::ZERO, &policy));
+    }
+
+    #[test]
+    fn evicts_messages_by_their_original_age() {
+        let policy = HistoryPolicy {
+            max_messages: 10,
+            max_age: Some(Duration::from_millis(100)),
+        };
+        let mut history = TopicHistory::default();
+
+        // Pulled messages already past the maximum age are never kept
+        assert!(!history.insert(id(0), envelope(0), Duration::from_millis(150), &policy));
+
+        assert!(history.insert(id(2), envelope(2), Duration::ZERO, &policy));
+        assert!(history.insert(id(1), envelope(1), Duration::from_millis(80), &policy));
+        let ages: Vec<_> = history.envelopes(&policy).into_iter().map(|(age, _)| age).collect();
+        assert_eq!(ages.len(), 2);
+        assert!(ages[1] >= Duration::from_millis(80));
+
+        std::thread::sleep(Duration::from_millis(40));
+        let kept: Vec<_> = history
+            .envelopes(&policy)
+            .into_iter()
+            .map(|(_, envelope)| envelope)
+            .collect();
+        assert_eq!(kept, vec![envelope(2)]);
+    }
+}
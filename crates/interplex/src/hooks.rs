@@ -0,0 +1,414 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{Arc, Mutex, RwLock, Weak},
+};
+
+use libp2p::{
+    futures::{future::BoxFuture, FutureExt},
+    PeerId,
+};
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::ipc::{NodeEvent, StreamRole};
+
+/// The kind of a [`NodeEvent`], without its data
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    StreamOpened,
+    StreamClosed,
+    SubscribedMessage,
+    RejectedMessage,
+    PeerSubscribed,
+    PeerUnsubscribed,
+    DiscoveredPeers,
+    LostPeer,
+}
+
+impl NodeEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            NodeEvent::StreamOpened { .. } => EventKind::StreamOpened,
+            NodeEvent::StreamClosed { .. } => EventKind::StreamClosed,
+            NodeEvent::SubscribedMessage { .. } => EventKind::SubscribedMessage,
+            NodeEvent::RejectedMessage { .. } => EventKind::RejectedMessage,
+            NodeEvent::PeerSubscribed { .. } => EventKind::PeerSubscribed,
+            NodeEvent::PeerUnsubscribed { .. } => EventKind::PeerUnsubscribed,
+            NodeEvent::DiscoveredPeers(_) => EventKind::DiscoveredPeers,
+            NodeEvent::LostPeer(_) => EventKind::LostPeer,
+        }
+    }
+}
+
+/// Selects which events a hook is called for.
+///
+/// Every criterion that is set must match. Events that a criterion does not apply to (ie a topic
+/// filter and a stream event) never match it.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    kinds: Option<HashSet<EventKind>>,
+    peer: Option<PeerId>,
+    topic: Option<String>,
+    group: Option<String>,
+    role: Option<StreamRole>,
+}
+
+impl EventFilter {
+    /// Matches every event
+    pub fn any() -> Self {
+        Self::default()
+    }
+
+    /// Adds an event kind to match. May be called multiple times to match several kinds.
+    pub fn kind(mut self, kind: EventKind) -> Self {
+        self.kinds.get_or_insert_default().insert(kind);
+        self
+    }
+
+    /// Only match events concerning a specific remote peer
+    pub fn peer(mut self, peer: PeerId) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Only match messages received on a specific topic
+    pub fn topic(mut self, topic: impl Into<String>) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Only match peer events, and messages signed by a sender, concerning a specific group. The
+    /// group of a sender that wasn't discovered is the one it claims (see [`crate::SenderIdentity`]).
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Only match stream events where the local node has a specific role
+    pub fn role(mut self, role: StreamRole) -> Self {
+        self.role = Some(role);
+        self
+    }
+
+    pub fn matches(&self, event: &NodeEvent) -> bool {
+        if let Some(kinds) = &self.kinds {
+            if !kinds.contains(&event.kind()) {
+                return false;
+            }
+        }
+
+        if let Some(peer) = &self.peer {
+            let matched = match event {
+                NodeEvent::StreamOpened { remote, .. } | NodeEvent::StreamClosed { remote, .. } => {
+                    remote == peer
+                }
+                NodeEvent::SubscribedMessage { source, .. }
+                | NodeEvent::RejectedMessage { source, .. } => source == peer,
+                NodeEvent::PeerSubscribed { peer: p, .. }
+                | NodeEvent::PeerUnsubscribed { peer: p, .. } => p == peer,
+                NodeEvent::DiscoveredPeers(peers) => peers.contains_key(peer),
+                NodeEvent::LostPeer(identity) => &identity.peer_id == peer,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(topic) = &self.topic {
+            let matched = match event {
+                NodeEvent::SubscribedMessage { topics, .. } => topics.contains(topic),
+                NodeEvent::RejectedMessage { topic: t, .. }
+                | NodeEvent::PeerSubscribed { topic: t, .. }
+                | NodeEvent::PeerUnsubscribed { topic: t, .. } => t == topic,
+                _ => false,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(group) = &self.group {
+            let matched = match event {
+                NodeEvent::SubscribedMessage {
+                    sender: Some(sender),
+                    ..
+                } => &sender.group() == group,
+                NodeEvent::PeerSubscribed {
+                    identity: Some(identity),
+                    ..
+                }
+                | NodeEvent::PeerUnsubscribed {
+                    identity: Some(identity),
+                    ..
+                } => &identity.group() == group,
+                NodeEvent::DiscoveredPeers(peers) => {
+                    peers.values().any(|identity| &identity.group() == group)
+                }
+                NodeEvent::LostPeer(identity) => &identity.group() == group,
+                _ => false,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if let Some(role) = &self.role {
+            let matched = match event {
+                NodeEvent::StreamOpened { role: r, .. } | NodeEvent::StreamClosed { role: r, .. } => {
+                    r == role
+                }
+                _ => false,
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Values that a hook may return. Errors are logged and otherwise ignored.
+pub trait HookOutput: Send + 'static {
+    fn into_result(self) -> Result<(), String>;
+}
+
+impl HookOutput for () {
+    fn into_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: Debug + Send + 'static> HookOutput for Result<(), E> {
+    fn into_result(self) -> Result<(), String> {
+        self.map_err(|e| format!("{e:?}"))
+    }
+}
+
+type HookFn = Arc<dyn Fn(NodeEvent) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+struct Hook {
+    filter: EventFilter,
+    callback: HookFn,
+}
+
+#[derive(Default)]
+struct HookRegistry {
+    hooks: RwLock<HashMap<Uuid, Hook>>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+/// Shared registry of event hooks, dispatched to by the network event loop
+#[derive(Clone, Default)]
+pub(crate) struct Hooks(Arc<HookRegistry>);
+
+impl Hooks {
+    pub fn register<F, Fut>(&self, filter: EventFilter, hook: F) -> HookHandle
+    where
+        F: Fn(NodeEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
+    {
+        let id = Uuid::new_v4();
+        let callback: HookFn =
+            Arc::new(move |event| hook(event).map(HookOutput::into_result).boxed());
+        self.0
+            .hooks
+            .write()
+            .unwrap()
+            .insert(id, Hook { filter, callback });
+
+        HookHandle {
+            id,
+            registry: Arc::downgrade(&self.0),
+        }
+    }
+
+    /// Spawns every hook matching the event as its own task, so that failing or panicking hooks
+    /// cannot affect the event loop or each other.
+    pub fn dispatch(&self, event: &NodeEvent) {
+        let matching: Vec<(Uuid, HookFn)> = self
+            .0
+            .hooks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, hook)| hook.filter.matches(event))
+            .map(|(id, hook)| (*id, hook.callback.clone()))
+            .collect();
+
+        if matching.is_empty() {
+            return;
+        }
+
+        let mut tasks = self.0.tasks.lock().unwrap();
+        while tasks.try_join_next().is_some() {}
+        for (id, callback) in matching {
+            let event = event.clone();
+            tasks.spawn(async move {
+                match AssertUnwindSafe(callback(event)).catch_unwind().await {
+                    Ok(Ok(())) => (),
+                    Ok(Err(error)) => tracing::warn!(hook = %id, "Event hook failed: {error}"),
+                    Err(_) => tracing::warn!(hook = %id, "Event hook panicked"),
+                }
+            });
+        }
+    }
+}
+
+/// Keeps a hook registered. The hook is removed when this handle is dropped or unregistered.
+pub struct HookHandle {
+    id: Uuid,
+    registry: Weak<HookRegistry>,
+}
+
+impl HookHandle {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn unregister(self) {
+        drop(self)
+    }
+}
+
+impl Drop for HookHandle {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.hooks.write().unwrap().remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interplex_common::identification::{NodeBuilder, NodeIdentifier};
+    use libp2p::bytes::Bytes;
+
+    use super::*;
+
+    fn identifier(peer: PeerId, group: &str) -> NodeIdentifier {
+        NodeBuilder::new_from_id("test", peer)
+            .group(group)
+            .build()
+            .unwrap()
+    }
+
+    fn stream(remote: PeerId, role: StreamRole) -> NodeEvent {
+        NodeEvent::StreamOpened {
+            stream_id: Uuid::new_v4(),
+            remote,
+            role,
+        }
+    }
+
+    fn message(source: PeerId, group: &str, topic: &str) -> NodeEvent {
+        NodeEvent::SubscribedMessage {
+            source,
+            sender: Some((&identifier(source, group)).into()),
+            data: Bytes::from_static(b"data"),
+            topics: vec![String::from(topic)],
+        }
+    }
+
+    fn subscribed(peer: PeerId, group: Option<&str>, topic: &str) -> NodeEvent {
+        NodeEvent::PeerSubscribed {
+            peer,
+            identity: group.map(|group| identifier(peer, group)),
+            topic: String::from(topic),
+        }
+    }
+
+    #[test]
+    fn matches_kinds() {
+        let peer = PeerId::random();
+        let filter = EventFilter::any()
+            .kind(EventKind::StreamOpened)
+            .kind(EventKind::LostPeer);
+
+        assert!(EventFilter::any().matches(&message(peer, "default", "chat")));
+        assert!(filter.matches(&stream(peer, StreamRole::Source)));
+        assert!(filter.matches(&NodeEvent::LostPeer(identifier(peer, "default"))));
+        assert!(!filter.matches(&message(peer, "default", "chat")));
+    }
+
+    #[test]
+    fn matches_peer() {
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let filter = EventFilter::any().peer(peer);
+
+        assert!(filter.matches(&stream(peer, StreamRole::Sink)));
+        assert!(filter.matches(&message(peer, "default", "chat")));
+        assert!(filter.matches(&subscribed(peer, None, "chat")));
+        assert!(filter.matches(&NodeEvent::DiscoveredPeers(HashMap::from([
+            (other, identifier(other, "default")),
+            (peer, identifier(peer, "default")),
+        ]))));
+        assert!(!filter.matches(&stream(other, StreamRole::Sink)));
+        assert!(!filter.matches(&message(other, "default", "chat")));
+        assert!(!filter.matches(&NodeEvent::LostPeer(identifier(other, "default"))));
+    }
+
+    #[test]
+    fn matches_topic() {
+        let peer = PeerId::random();
+        let filter = EventFilter::any().topic("chat");
+
+        assert!(filter.matches(&message(peer, "default", "chat")));
+        assert!(filter.matches(&subscribed(peer, None, "chat")));
+        assert!(filter.matches(&NodeEvent::RejectedMessage {
+            source: peer,
+            topic: String::from("chat"),
+            reason: String::from("invalid"),
+        }));
+        assert!(!filter.matches(&message(peer, "default", "news")));
+        assert!(!filter.matches(&stream(peer, StreamRole::Source)));
+    }
+
+    #[test]
+    fn matches_group() {
+        let peer = PeerId::random();
+        let filter = EventFilter::any().group("ops");
+
+        assert!(filter.matches(&message(peer, "ops", "chat")));
+        assert!(filter.matches(&subscribed(peer, Some("ops"), "chat")));
+        assert!(filter.matches(&NodeEvent::LostPeer(identifier(peer, "ops"))));
+        assert!(!filter.matches(&message(peer, "dev", "chat")));
+        assert!(!filter.matches(&subscribed(peer, None, "chat")));
+        assert!(!filter.matches(&stream(peer, StreamRole::Source)));
+    }
+
+    #[test]
+    fn matches_role() {
+        let peer = PeerId::random();
+        let filter = EventFilter::any().role(StreamRole::Sink);
+
+        assert!(filter.matches(&stream(peer, StreamRole::Sink)));
+        assert!(filter.matches(&NodeEvent::StreamClosed {
+            stream_id: Uuid::new_v4(),
+            remote: peer,
+            role: StreamRole::Sink,
+        }));
+        assert!(!filter.matches(&stream(peer, StreamRole::Source)));
+        assert!(!filter.matches(&message(peer, "default", "chat")));
+    }
+
+    #[test]
+    fn matches_every_criterion() {
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let filter = EventFilter::any()
+            .kind(EventKind::SubscribedMessage)
+            .peer(peer)
+            .topic("chat")
+            .group("ops");
+
+        assert!(filter.matches(&message(peer, "ops", "chat")));
+        assert!(!filter.matches(&message(other, "ops", "chat")));
+        assert!(!filter.matches(&message(peer, "dev", "chat")));
+        assert!(!filter.matches(&message(peer, "ops", "news")));
+        assert!(!filter.matches(&subscribed(peer, Some("ops"), "chat")));
+    }
+}
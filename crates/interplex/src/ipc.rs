@@ -2,48 +2,61 @@ use std::collections::HashMap;
 
 use async_channel::Sender;
 use interplex_common::identification::NodeIdentifier;
-use libp2p::{bytes::Bytes, Multiaddr, PeerId};
+use libp2p::{bytes::Bytes, Multiaddr, PeerId, StreamProtocol};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
-use crate::error::Error;
+use crate::{
+    envelope::SenderIdentity,
+    error::Error,
+    pubsub::{TopicMessage, TopicName},
+    stream::{InboundStreams, InterplexStream},
+};
 
-#[allow(dead_code)]
-#[derive(Clone, Debug)]
-pub(crate) enum StreamRole {
+/// Which side of a stream the local node is on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamRole {
+    /// The stream was opened by the local node
     Source,
+
+    /// The stream was opened by a remote peer
     Sink,
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub(crate) enum Command {
-    OpenStream(PeerId),
-    CloseStream(Uuid),
-    WriteStream { stream_id: Uuid, data: Vec<u8> },
-    ReadStream { stream_id: Uuid, buf_size: usize },
-    Subscribe(Vec<String>),
-    Unsubscribe(Vec<String>),
+    OpenStream { peer: PeerId, protocol: StreamProtocol },
+    Accept(StreamProtocol),
+    Subscribe(Vec<TopicName>),
+    Unsubscribe(Vec<TopicName>),
+    Publish { topic: TopicName, data: Bytes },
+    Messages(TopicName),
+    PullHistory { topic: TopicName, peer: PeerId },
+    TopicPeers(TopicName),
     ExitLoop,
     AddRendezvous(Multiaddr),
     RemoveRendezvous(PeerId),
-    UpdateRemotes(Option<String>),
+    RefreshPeers,
     ListPeers,
     GetPeer(PeerId)
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub(crate) enum CommandResponse {
-    OpenStream(Uuid),
-    CloseStream,
-    WriteStream(usize),
-    ReadStream { data: Vec<u8>, bytes_read: usize },
+    OpenStream(InterplexStream),
+    Accept(InboundStreams),
     Subscribe,
     Unsubscribe,
+    Publish,
+    Messages(broadcast::Receiver<TopicMessage>),
+    PullHistory(usize),
+    TopicPeers(Vec<NodeIdentifier>),
     ExitLoop,
     AddRendezvous(PeerId),
     RemoveRendezvous,
-    UpdateRemotes,
+    RefreshPeers,
     ListPeers(HashMap<PeerId, NodeIdentifier>),
     GetPeer(Option<NodeIdentifier>)
 }
@@ -55,24 +68,63 @@ pub(crate) struct CommandWrapper {
     pub command: Command,
 }
 
-#[allow(dead_code)]
+/// Events emitted by a running node, received through [`crate::InterplexNode::events`]
 #[derive(Clone, Debug)]
-pub(crate) enum Event {
+pub enum NodeEvent {
+    /// A stream to a remote peer was opened
     StreamOpened {
         stream_id: Uuid,
         remote: PeerId,
         role: StreamRole,
     },
+
+    /// A stream to a remote peer was closed
     StreamClosed {
         stream_id: Uuid,
         remote: PeerId,
         role: StreamRole,
     },
+
+    /// A message was received on a subscribed topic. Topics are given by the names they were
+    /// subscribed with, without their scope prefix.
+    ///
+    /// `sender` is the identity the message was signed with, which is missing only for messages
+    /// published anonymously. Only its peer ID is proven by the signature (see
+    /// [`crate::SenderIdentity`]).
     SubscribedMessage {
         source: PeerId,
+        sender: Option<SenderIdentity>,
         data: Bytes,
         topics: Vec<String>,
     },
+
+    /// A received message was dropped because its envelope failed verification. `source` is the
+    /// peer that delivered it, which may have only relayed it.
+    RejectedMessage {
+        source: PeerId,
+        topic: String,
+        reason: String,
+    },
+
+    /// A connected peer subscribed to a topic. The topic is given by the name it was subscribed
+    /// with if the local node is subscribed to it as well, or by its name on the network otherwise.
+    /// `identity` is known if the peer was discovered.
+    PeerSubscribed {
+        peer: PeerId,
+        identity: Option<NodeIdentifier>,
+        topic: String,
+    },
+
+    /// A peer unsubscribed from a topic, or disconnected while subscribed to it
+    PeerUnsubscribed {
+        peer: PeerId,
+        identity: Option<NodeIdentifier>,
+        topic: String,
+    },
+
+    /// Peers that were not previously known were discovered through a rendezvous node
     DiscoveredPeers(HashMap<PeerId, NodeIdentifier>),
+
+    /// A peer is no longer registered with any known rendezvous node
     LostPeer(NodeIdentifier)
 }
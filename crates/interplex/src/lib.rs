@@ -3,9 +3,29 @@ mod node;
 mod ipc;
 mod error;
 mod netwrapper;
+mod hooks;
+mod stream;
+mod codec;
+mod rpc;
+mod config;
+mod pubsub;
+mod envelope;
+mod history;
 
-pub use node::{InterplexNode, SavedKey, NodeBuilder};
+pub use node::{InterplexNode, SavedKey, NodeBuilder, EVENT_BUFFER};
+pub use ipc::{NodeEvent, StreamRole};
+pub use stream::{InboundStreams, InterplexStream};
+pub use codec::{Format, FramedStream, DEFAULT_MAX_FRAME_SIZE};
+pub use rpc::{RemoteError, RemoteErrorKind, ServiceHandle, DEFAULT_REQUEST_TIMEOUT};
+pub use pubsub::{TopicMessage, TopicMessages, TopicName, TypedTopic, TOPIC_BUFFER};
+pub use hooks::{EventFilter, EventKind, HookHandle, HookOutput};
 pub use error::Error;
+pub use envelope::SenderIdentity;
+pub use history::TOPIC_HISTORY_PROTOCOL;
+pub use config::{
+    GossipsubSettings, HistoryPolicy, MessageIdMode, NetworkConfig, PubsubEngine, ReconnectPolicy,
+    TopicScope, ValidationMode,
+};
 
 // notes for future me
 // request/response with streams! Allows arbitrary-size thingies
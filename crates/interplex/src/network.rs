@@ -1,6 +1,11 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
 };
 
 use async_channel::{Receiver, Sender};
@@ -12,30 +17,53 @@ use interplex_common::{
 use libp2p::{
     autonat,
     floodsub::{self, FloodsubEvent, Topic},
-    futures::{AsyncReadExt, AsyncWriteExt as _, StreamExt},
+    bytes::Bytes,
+    futures::{self, future::join_all, StreamExt},
+    gossipsub::{self, IdentTopic, MessageAuthenticity},
     identify,
     identity::Keypair,
     multiaddr::Protocol,
     noise, ping, relay,
-    swarm::{NetworkBehaviour, SwarmEvent},
+    request_response::OutboundRequestId,
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        DialError, NetworkBehaviour, SwarmEvent,
+    },
     tcp, upnp, yamux, Multiaddr, PeerId, Stream, StreamProtocol, Swarm, SwarmBuilder,
 };
 use tokio::{
     select,
-    sync::Mutex,
-    task::{JoinHandle, JoinSet},
+    sync::{broadcast, mpsc, Mutex, MutexGuard, Notify},
+    task::{AbortHandle, JoinHandle, JoinSet},
+    time::{interval, sleep_until, Instant, Interval, MissedTickBehavior},
 };
+use libp2p_stream::{Control, IncomingStreams};
 use uuid::Uuid;
 
 use crate::{
+    config::{GossipsubSettings, MessageIdMode, NetworkConfig, PubsubEngine, ValidationMode},
+    codec::{read_frame, write_frame, DEFAULT_MAX_FRAME_SIZE},
+    envelope::{
+        content_digest, initial_seqno, Envelope, MessageId, OpenedEnvelope, SenderIdentity,
+    },
     error::{CResult, Error},
-    ipc::{Command, CommandResponse, CommandWrapper, Event, StreamRole},
+    history::{
+        serve_history, HistoryRequest, HistoryResponse, TopicHistory, HISTORY_FORMAT,
+        TOPIC_HISTORY_PROTOCOL,
+    },
+    hooks::Hooks,
+    ipc::{Command, CommandResponse, CommandWrapper, NodeEvent, StreamRole},
+    pubsub::{in_scope, TopicMessage, TopicName, TOPIC_BUFFER},
+    rpc::DEFAULT_REQUEST_TIMEOUT,
+    stream::{InboundStreams, InterplexStream, SharedStream},
 };
 
 #[derive(NetworkBehaviour)]
 pub(crate) struct NodeBehaviour {
     rendezvous: interplex_common::rendezvous::client::Behaviour,
-    floodsub: floodsub::Floodsub,
+    floodsub: Toggle<floodsub::Floodsub>,
+    gossipsub: Toggle<gossipsub::Behaviour>,
     autonat: autonat::Behaviour,
     identify: identify::Behaviour,
     stream: libp2p_stream::Behaviour,
@@ -44,28 +72,159 @@ pub(crate) struct NodeBehaviour {
     relay: relay::client::Behaviour,
 }
 
+impl NodeBehaviour {
+    /// Subscribes to a topic with the enabled pubsub engine, returning whether the node was not
+    /// subscribed already
+    fn subscribe(&mut self, topic: &str) -> bool {
+        if let Some(gossipsub) = self.gossipsub.as_mut() {
+            match gossipsub.subscribe(&IdentTopic::new(topic)) {
+                Ok(subscribed) => subscribed,
+                Err(error) => {
+                    tracing::warn!(topic, "Failed to subscribe: {error}");
+                    false
+                }
+            }
+        } else if let Some(floodsub) = self.floodsub.as_mut() {
+            floodsub.subscribe(Topic::new(topic))
+        } else {
+            false
+        }
+    }
+
+    /// Publishes a message with the enabled pubsub engine, whether or not the node is subscribed
+    fn publish(&mut self, topic: &str, data: Bytes) -> CResult<()> {
+        if let Some(gossipsub) = self.gossipsub.as_mut() {
+            gossipsub
+                .publish(IdentTopic::new(topic), data.to_vec())
+                .map(|_| ())
+                .map_err(|error| Error::publish(topic, error))
+        } else if let Some(floodsub) = self.floodsub.as_mut() {
+            floodsub.publish_any(Topic::new(topic), data);
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Unsubscribes from a topic with the enabled pubsub engine, returning whether the node was
+    /// subscribed
+    fn unsubscribe(&mut self, topic: &str) -> bool {
+        if let Some(gossipsub) = self.gossipsub.as_mut() {
+            gossipsub.unsubscribe(&IdentTopic::new(topic))
+        } else if let Some(floodsub) = self.floodsub.as_mut() {
+            floodsub.unsubscribe(Topic::new(topic))
+        } else {
+            false
+        }
+    }
+}
+
+/// Remote peer, role and shared state of a stream that has not been closed yet
+type OpenStream = (PeerId, StreamRole, Arc<SharedStream>);
+
 #[derive(Clone)]
 pub(crate) struct NetworkHandler {
     commands: Receiver<CommandWrapper>,
-    events: Sender<Event>,
+    events: broadcast::Sender<NodeEvent>,
+    hooks: Hooks,
     swarm: Arc<Mutex<Swarm<NodeBehaviour>>>,
+    swarm_wanted: Arc<Notify>,
     identifier: NodeIdentifier,
-    topics: Arc<Mutex<Vec<String>>>,
-    streams: Arc<Mutex<HashMap<Uuid, (PeerId, StreamRole, Arc<Mutex<Stream>>)>>>,
+    keypair: Keypair,
+    seqno: Arc<AtomicU64>,
+    topics: Arc<Mutex<HashMap<String, String>>>,
+    topic_streams: Arc<Mutex<HashMap<String, broadcast::Sender<TopicMessage>>>>,
+    history: Arc<Mutex<HashMap<String, TopicHistory>>>,
+    history_incoming: Arc<std::sync::Mutex<Option<IncomingStreams>>>,
+    subscribers: Arc<Mutex<HashMap<String, HashSet<PeerId>>>>,
+    streams: Arc<Mutex<HashMap<Uuid, OpenStream>>>,
+    dropped_streams: (Sender<Uuid>, Receiver<Uuid>),
     rendezvous_points: Arc<Mutex<HashMap<PeerId, Multiaddr>>>,
     peers: Arc<Mutex<HashMap<PeerId, (HashSet<PeerId>, NodeIdentifier)>>>,
+    discoveries: Arc<Mutex<HashSet<OutboundRequestId>>>,
+    pubsub_peers: Arc<Mutex<HashSet<PeerId>>>,
+    control: Control,
+    config: NetworkConfig,
+    reconnects: Arc<std::sync::Mutex<Reconnects>>,
+}
+
+/// Redials scheduled for rendezvous points whose connection was lost
+#[derive(Default)]
+struct Reconnects {
+    attempts: HashMap<PeerId, u32>,
+    pending: HashMap<PeerId, AbortHandle>,
+    tasks: JoinSet<()>,
 }
 
 enum EventType {
     Swarm(SwarmEvent<NodeBehaviourEvent>),
     Command(CommandWrapper),
-    Stream(PeerId, Stream),
+    StreamDropped(Uuid),
+    RefreshPeers,
 }
 
+/// Number of incoming streams that may be waiting to be accepted before new ones are dropped
+pub(crate) const INCOMING_STREAM_BUFFER: usize = 64;
+
+/// How long shutdown waits for deregistrations, stream closes and running handlers
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl NetworkHandler {
+    fn make_gossipsub(
+        key: &Keypair,
+        settings: &GossipsubSettings,
+    ) -> Result<gossipsub::Behaviour, Box<dyn std::error::Error + Send + Sync>> {
+        let mut config = gossipsub::ConfigBuilder::default();
+        config
+            .mesh_n(settings.mesh_n)
+            .mesh_n_low(settings.mesh_n_low)
+            .mesh_n_high(settings.mesh_n_high)
+            .mesh_outbound_min(settings.mesh_outbound_min)
+            .gossip_lazy(settings.gossip_lazy)
+            .heartbeat_interval(settings.heartbeat_interval)
+            .history_length(settings.history_length)
+            .history_gossip(settings.history_gossip)
+            .max_transmit_size(settings.max_transmit_size)
+            .duplicate_cache_time(settings.duplicate_cache_time)
+            .validation_mode(match settings.validation {
+                ValidationMode::Strict => gossipsub::ValidationMode::Strict,
+                ValidationMode::Permissive => gossipsub::ValidationMode::Permissive,
+                ValidationMode::Anonymous => gossipsub::ValidationMode::Anonymous,
+                ValidationMode::None => gossipsub::ValidationMode::None,
+            })
+            // Envelopes are verified before messages are forwarded
+            .validate_messages();
+
+        if settings.message_id == MessageIdMode::Content {
+            config.message_id_fn(|message: &gossipsub::Message| {
+                // Envelopes differ by sequence number, so only the message they wrap is hashed,
+                // along with its source so that publishers sending the same data are kept apart
+                let envelope = Envelope::decode(&message.data);
+                let data = match &envelope {
+                    Ok(envelope) => envelope.data(),
+                    Err(_) => &message.data,
+                };
+                let source = message.source.map(|source| source.to_bytes()).unwrap_or_default();
+                gossipsub::MessageId::new(&content_digest(
+                    message.topic.as_str(),
+                    &[&(source.len() as u64).to_be_bytes(), &source, data],
+                ))
+            });
+        }
+
+        // Anonymous validation rejects signed messages, so nothing published may be signed either
+        let authenticity = match settings.validation {
+            ValidationMode::Anonymous => MessageAuthenticity::Anonymous,
+            _ => MessageAuthenticity::Signed(key.clone()),
+        };
+
+        Ok(gossipsub::Behaviour::new(authenticity, config.build()?)?)
+    }
+
     fn make_swarm(
         identification: NodeIdentifier,
         keypair: Keypair,
+        config: &NetworkConfig,
     ) -> Result<Swarm<NodeBehaviour>, Box<dyn std::error::Error>> {
         Ok(SwarmBuilder::with_existing_identity(keypair)
             .with_tokio()
@@ -76,33 +235,56 @@ impl NetworkHandler {
             )?
             .with_dns()?
             .with_relay_client(noise::Config::new, yamux::Config::default)?
-            .with_behaviour(|key, relay_client| NodeBehaviour {
-                rendezvous: interplex_common::rendezvous::client::Behaviour::new(
-                    identification.clone(),
-                ),
-                floodsub: floodsub::Floodsub::new(key.public().to_peer_id()),
-                autonat: autonat::Behaviour::new(
-                    key.public().to_peer_id(),
-                    autonat::Config::default(),
-                ),
-                identify: identify::Behaviour::new(identify::Config::new(
-                    String::from("/interplex"),
-                    key.public(),
-                )),
-                stream: libp2p_stream::Behaviour::default(),
-                upnp: upnp::tokio::Behaviour::default(),
-                ping: ping::Behaviour::default(),
-                relay: relay_client,
-            })?
+            .with_behaviour(
+                |key, relay_client| -> Result<NodeBehaviour, Box<dyn std::error::Error + Send + Sync>> {
+                    let gossipsub = match config.pubsub {
+                        PubsubEngine::Gossipsub => Some(Self::make_gossipsub(key, &config.gossipsub)?),
+                        PubsubEngine::Floodsub => None,
+                    };
+
+                    let mut rendezvous = interplex_common::rendezvous::client::Behaviour::new(
+                        identification.clone(),
+                        key.clone(),
+                    );
+                    rendezvous.set_ttl(
+                        config
+                            .registration_ttl
+                            .and_then(|ttl| chrono::TimeDelta::from_std(ttl).ok()),
+                    );
+
+                    Ok(NodeBehaviour {
+                        rendezvous,
+                        floodsub: Toggle::from(
+                            (config.pubsub == PubsubEngine::Floodsub)
+                                .then(|| floodsub::Floodsub::new(key.public().to_peer_id())),
+                        ),
+                        gossipsub: Toggle::from(gossipsub),
+                        autonat: autonat::Behaviour::new(
+                            key.public().to_peer_id(),
+                            autonat::Config::default(),
+                        ),
+                        identify: identify::Behaviour::new(identify::Config::new(
+                            String::from("/interplex"),
+                            key.public(),
+                        )),
+                        stream: libp2p_stream::Behaviour::default(),
+                        upnp: upnp::tokio::Behaviour::default(),
+                        ping: ping::Behaviour::default(),
+                        relay: relay_client,
+                    })
+                },
+            )?
             .build())
     }
 
     pub fn new(
         command_rcv: Receiver<CommandWrapper>,
-        event_send: Sender<Event>,
+        event_send: broadcast::Sender<NodeEvent>,
+        hooks: Hooks,
         identification: NodeIdentifier,
         rendezvous_nodes: Vec<Multiaddr>,
         keypair: Keypair,
+        config: NetworkConfig,
     ) -> IResult<Self> {
         let mut rendezvous_points: HashMap<PeerId, Multiaddr> = HashMap::new();
         for rdv in rendezvous_nodes.clone() {
@@ -110,7 +292,7 @@ impl NetworkHandler {
                 .iter()
                 .filter_map(|p| {
                     if let Protocol::P2p(peer) = p {
-                        Some(peer.clone())
+                        Some(peer)
                     } else {
                         None
                     }
@@ -128,70 +310,743 @@ impl NetworkHandler {
             }
         }
 
-        let mut swarm = Self::make_swarm(identification.clone(), keypair)
+        let mut swarm = Self::make_swarm(identification.clone(), keypair.clone(), &config)
             .or_else(|e| Err(InterplexError::wrap(e)))?;
 
-        for rdv in rendezvous_nodes.clone() {
-            swarm.dial(rdv).or_else(|e| Err(InterplexError::wrap(e)))?;
-        }
+        swarm
+            .listen_on(
+                "/ip4/0.0.0.0/tcp/0"
+                    .parse()
+                    .expect("Default listen address is valid"),
+            )
+            .or_else(|e| Err(InterplexError::wrap(e)))?;
+
+        let mut control = swarm.behaviour().stream.new_control();
+        let history_incoming = match config.topic_history {
+            Some(_) => Some(
+                control
+                    .accept(TOPIC_HISTORY_PROTOCOL)
+                    .map_err(InterplexError::wrap)?,
+            ),
+            None => None,
+        };
 
         Ok(Self {
             commands: command_rcv,
             events: event_send,
+            hooks,
             identifier: identification.clone(),
+            keypair,
+            seqno: Arc::new(AtomicU64::new(initial_seqno())),
             swarm: Arc::new(Mutex::new(swarm)),
-            topics: Arc::new(Mutex::new(Vec::new())),
+            swarm_wanted: Arc::new(Notify::new()),
+            topics: Arc::new(Mutex::new(HashMap::new())),
+            topic_streams: Arc::new(Mutex::new(HashMap::new())),
+            history: Arc::new(Mutex::new(HashMap::new())),
+            history_incoming: Arc::new(std::sync::Mutex::new(history_incoming)),
+            subscribers: Arc::new(Mutex::new(HashMap::new())),
             streams: Arc::new(Mutex::new(HashMap::new())),
+            dropped_streams: async_channel::unbounded(),
             rendezvous_points: Arc::new(Mutex::new(rendezvous_points)),
             peers: Arc::new(Mutex::new(HashMap::new())),
+            discoveries: Arc::new(Mutex::new(HashSet::new())),
+            pubsub_peers: Arc::new(Mutex::new(HashSet::new())),
+            control,
+            config,
+            reconnects: Default::default(),
         })
     }
 
+    /// Dials a rendezvous point by its peer ID, so that failures can be traced back to it
+    fn dial_rendezvous(
+        swarm: &mut Swarm<NodeBehaviour>,
+        peer: PeerId,
+        address: Multiaddr,
+    ) -> Result<(), DialError> {
+        swarm.dial(
+            DialOpts::peer_id(peer)
+                .addresses(vec![address])
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build(),
+        )
+    }
+
+    /// Schedules a redial of a disconnected rendezvous point, backing off further after every
+    /// failed attempt until the reconnect policy gives up
+    fn schedule_redial(&self, peer: PeerId, address: Multiaddr) {
+        let policy = &self.config.reconnect;
+        let mut reconnects = self.reconnects.lock().unwrap();
+        while reconnects.tasks.try_join_next().is_some() {}
+        if reconnects
+            .pending
+            .get(&peer)
+            .is_some_and(|task| !task.is_finished())
+        {
+            return;
+        }
+
+        let attempts = reconnects.attempts.entry(peer).or_insert(0);
+        let attempt = *attempts;
+        if policy.max_attempts.is_some_and(|max| attempt >= max) {
+            tracing::warn!(%peer, "Giving up on rendezvous point after {attempt} failed redial(s)");
+            return;
+        }
+        *attempts += 1;
+
+        let delay = policy.delay(attempt);
+        tracing::debug!(%peer, ?delay, "Redialing rendezvous point");
+        let cself = self.clone();
+        let task = reconnects.tasks.spawn(async move {
+            tokio::time::sleep(delay).await;
+            cself.reconnects.lock().unwrap().pending.remove(&peer);
+            if !cself.rendezvous_points.lock().await.contains_key(&peer) {
+                return;
+            }
+
+            let result = Self::dial_rendezvous(&mut *cself.lock_swarm().await, peer, address.clone());
+            match result {
+                Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => (),
+                Err(error) => {
+                    tracing::warn!(%peer, "Failed to redial rendezvous point: {error}");
+                    cself.schedule_redial(peer, address);
+                }
+            }
+        });
+        reconnects.pending.insert(peer, task);
+    }
+
+    /// Asks a rendezvous point for the peers in the discovery group
+    async fn discover_from(&self, swarm: &mut Swarm<NodeBehaviour>, rendezvous_node: &PeerId) {
+        let request = swarm
+            .behaviour_mut()
+            .rendezvous
+            .discover_all(
+                rendezvous_node,
+                self.config.discovery_group.clone(),
+                self.config.discovery_query.clone(),
+            );
+        self.discoveries.lock().await.insert(request);
+    }
+
+    /// Asks every connected rendezvous point for the peers in the discovery group
+    async fn refresh_peers(&self, swarm: &mut Swarm<NodeBehaviour>) {
+        let rendezvous_nodes: Vec<PeerId> =
+            self.rendezvous_points.lock().await.keys().copied().collect();
+        for rendezvous_node in rendezvous_nodes {
+            if swarm.is_connected(&rendezvous_node) {
+                self.discover_from(swarm, &rendezvous_node).await;
+            }
+        }
+    }
+
+    /// Updates the known peers with the result of a discovery, returning the peers that were not
+    /// known before and the peers that were lost. Peers in the discovery group that the rendezvous
+    /// point no longer lists are forgotten from it, and are lost once no rendezvous point lists
+    /// them.
+    async fn apply_discovery(
+        &self,
+        rendezvous_node: PeerId,
+        registrations: Vec<rendezvous::registrations::Registration>,
+        complete: bool,
+    ) -> (HashMap<PeerId, NodeIdentifier>, Vec<NodeIdentifier>) {
+        let mut known = self.peers.lock().await;
+        let listed: HashSet<PeerId> = registrations
+            .iter()
+            .map(|registration| registration.identity.peer_id)
+            .collect();
+
+        let mut discovered: HashMap<PeerId, NodeIdentifier> = HashMap::new();
+        for registration in registrations {
+            let identity = registration.identity;
+            match known.get_mut(&identity.peer_id) {
+                Some((rendezvous_nodes, current)) => {
+                    rendezvous_nodes.insert(rendezvous_node);
+                    *current = identity;
+                }
+                None => {
+                    known.insert(
+                        identity.peer_id,
+                        (HashSet::from([rendezvous_node]), identity.clone()),
+                    );
+                    discovered.insert(identity.peer_id, identity);
+                }
+            }
+        }
+
+        let mut lost: Vec<NodeIdentifier> = Vec::new();
+        if complete {
+            let group = self.config.discovery_group.as_ref();
+            known.retain(|peer, (rendezvous_nodes, identity)| {
+                if listed.contains(peer)
+                    || !rendezvous_nodes.contains(&rendezvous_node)
+                    || group.is_some_and(|group| &identity.group() != group)
+                {
+                    return true;
+                }
+
+                rendezvous_nodes.remove(&rendezvous_node);
+                if rendezvous_nodes.is_empty() {
+                    lost.push(identity.clone());
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
+        (discovered, lost)
+    }
+
+    /// Connects to discovered peers and adds them to the pubsub view, until the peer budget is
+    /// used up. Gossipsub builds its mesh from connected peers, so connecting is enough for it.
+    async fn peer_with(
+        &self,
+        swarm: &mut Swarm<NodeBehaviour>,
+        candidates: impl IntoIterator<Item = PeerId>,
+    ) {
+        let mut peered = self.pubsub_peers.lock().await;
+        for peer in candidates {
+            if peered.len() >= self.config.peer_budget {
+                break;
+            }
+            if peer == *swarm.local_peer_id() || !peered.insert(peer) {
+                continue;
+            }
+
+            if let Some(floodsub) = swarm.behaviour_mut().floodsub.as_mut() {
+                // Floodsub dials peers added to its view by itself
+                floodsub.add_node_to_partial_view(peer);
+            } else {
+                match swarm.dial(
+                    DialOpts::peer_id(peer)
+                        .condition(PeerCondition::DisconnectedAndNotDialing)
+                        .build(),
+                ) {
+                    Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => (),
+                    Err(error) => {
+                        tracing::debug!(%peer, "Failed to dial discovered peer: {error}");
+                        peered.remove(&peer);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Frees the budget place of a peer whose connection was lost. Floodsub keeps the peer in
+    /// its view and redials it by itself, so only peers dialed for gossipsub are released here,
+    /// to be dialed again when they are next discovered.
+    async fn disconnected(&self, swarm: &mut Swarm<NodeBehaviour>, peer: &PeerId) {
+        let floodsub = swarm.behaviour().floodsub.is_enabled();
+        if !floodsub {
+            self.pubsub_peers.lock().await.remove(peer);
+        }
+    }
+
+    /// Removes a peer from the pubsub view, freeing its place in the peer budget
+    async fn unpeer(&self, swarm: &mut Swarm<NodeBehaviour>, peer: &PeerId) {
+        if self.pubsub_peers.lock().await.remove(peer) {
+            if let Some(floodsub) = swarm.behaviour_mut().floodsub.as_mut() {
+                floodsub.remove_node_from_partial_view(peer);
+            }
+        }
+    }
+
+    /// Cancels any pending redial of a rendezvous point and resets its backoff
+    fn cancel_redial(&self, peer: &PeerId) {
+        let mut reconnects = self.reconnects.lock().unwrap();
+        reconnects.attempts.remove(peer);
+        if let Some(task) = reconnects.pending.remove(peer) {
+            task.abort();
+        }
+    }
+
+    /// Publishes an event to all subscribers and matching hooks. Having no subscribers is not an error.
+    fn emit(&self, event: NodeEvent) {
+        self.hooks.dispatch(&event);
+        let _ = self.events.send(event);
+    }
+
+    /// Locks the swarm, asking the event loop to release it if it is waiting for the next event
+    async fn lock_swarm(&self) -> MutexGuard<'_, Swarm<NodeBehaviour>> {
+        let lock = self.swarm.lock();
+        tokio::pin!(lock);
+        if let Poll::Ready(guard) = futures::poll!(&mut lock) {
+            return guard;
+        }
+
+        // The lock is queued before notifying, so it is handed over as soon as the loop releases it
+        self.swarm_wanted.notify_one();
+        lock.await
+    }
+
+    /// Polls the swarm for its next event, giving up at the deadline
+    async fn next_swarm_event(&self, deadline: Instant) -> Option<SwarmEvent<NodeBehaviourEvent>> {
+        loop {
+            let mut swarm = self.swarm.lock().await;
+            select! {
+                event = swarm.select_next_some() => return Some(event),
+                _ = self.swarm_wanted.notified() => continue,
+                _ = sleep_until(deadline) => return None,
+            }
+        }
+    }
+
+    /// Name of a topic on the network, scoped to the local node's namespace or group
+    fn resolve_topic(&self, topic: &TopicName) -> String {
+        topic.resolve(self.config.topic_scope, &self.identifier)
+    }
+
+    /// Whether messages are published without a signature, as anonymous gossipsub requires
+    fn anonymous(&self) -> bool {
+        self.config.pubsub == PubsubEngine::Gossipsub
+            && self.config.gossipsub.validation == ValidationMode::Anonymous
+    }
+
+    /// Wraps a message published on a resolved topic in a signed envelope
+    fn seal(&self, resolved: &str, data: Bytes) -> CResult<(MessageId, Bytes)> {
+        let seqno = self.seqno.fetch_add(1, Ordering::Relaxed);
+        let signer = (!self.anonymous())
+            .then(|| (&self.keypair, SenderIdentity::from(&self.identifier)));
+        let envelope = Envelope::seal(resolved, seqno, data, signer)?;
+        Ok((envelope.id(resolved), envelope.encode()?))
+    }
+
+    /// Decodes and verifies an envelope received on a resolved topic, and checks the identity it was
+    /// signed with against the one its sender was discovered with and the scope of the topic
+    async fn open(&self, resolved: &str, data: &[u8]) -> Result<OpenedEnvelope, String> {
+        let mut opened = Envelope::decode(data)?.open(resolved, !self.anonymous())?;
+        let Some(sender) = opened.sender.as_mut() else {
+            return Ok(opened);
+        };
+
+        // The signature only binds the peer ID, the rest of the identity is claimed by the sender
+        if let Some((_, discovered)) = self.peers.lock().await.get(&sender.peer_id) {
+            if discovered.namespace != sender.namespace {
+                return Err(format!(
+                    "Sender {} claims namespace {} but was discovered in {}",
+                    sender.peer_id, sender.namespace, discovered.namespace
+                ));
+            }
+
+            sender.alias = discovered.alias.clone();
+            sender.group = discovered.group.clone();
+        }
+
+        if !in_scope(resolved, sender) {
+            return Err(format!(
+                "Sender {} of namespace {} and group {} is outside the scope of the topic",
+                sender.peer_id,
+                sender.namespace,
+                sender.group()
+            ));
+        }
+
+        Ok(opened)
+    }
+
+    /// Name a resolved topic was subscribed with, or the resolved name if it isn't subscribed
+    async fn topic_name(&self, resolved: &str) -> String {
+        self.topics
+            .lock()
+            .await
+            .get(resolved)
+            .cloned()
+            .unwrap_or_else(|| resolved.to_string())
+    }
+
+    /// Adds a message that is already `age` old to the history of a subscribed topic, if history is
+    /// kept. Returns false if the message was already kept, or is too old to be kept.
+    async fn remember(
+        &self,
+        resolved: &str,
+        id: MessageId,
+        envelope: Bytes,
+        age: Duration,
+    ) -> bool {
+        let Some(policy) = &self.config.topic_history else {
+            return true;
+        };
+
+        self.history
+            .lock()
+            .await
+            .entry(resolved.to_string())
+            .or_default()
+            .insert(id, envelope, age, policy)
+    }
+
+    /// Forwards a verified message received on a subscribed topic to the streams of that topic, and
+    /// emits it as an event. The topic is delivered by the name it was subscribed with. Messages
+    /// signed by their sender are attributed to it rather than to `source`. `age` is the time since
+    /// the message was first received, which is zero unless it was pulled from a peer's history.
+    ///
+    /// Returns false if the topic isn't subscribed, or the message is already in its history (or
+    /// too old for it).
+    async fn deliver(
+        &self,
+        source: PeerId,
+        resolved: &str,
+        envelope: Bytes,
+        message: OpenedEnvelope,
+        age: Duration,
+    ) -> bool {
+        let Some(topic) = self.topics.lock().await.get(resolved).cloned() else {
+            return false;
+        };
+        if !self.remember(resolved, message.id, envelope, age).await {
+            return false;
+        }
+
+        let source = message
+            .sender
+            .as_ref()
+            .map(|sender| sender.peer_id)
+            .unwrap_or(source);
+
+        if let Some(stream) = self.topic_streams.lock().await.get(resolved) {
+            let _ = stream.send(TopicMessage {
+                source,
+                sender: message.sender.clone(),
+                seqno: message.seqno,
+                topic: topic.clone(),
+                data: message.data.clone(),
+            });
+        }
+
+        self.emit(NodeEvent::SubscribedMessage {
+            source,
+            sender: message.sender,
+            data: message.data,
+            topics: vec![topic],
+        });
+        true
+    }
+
+    /// Pulls the history of a resolved topic from a peer and delivers the messages that are new,
+    /// returning how many were delivered
+    async fn pull_history(&self, resolved: &str, peer: PeerId) -> CResult<usize> {
+        let exchange = async {
+            let mut stream = self
+                .control
+                .clone()
+                .open_stream(peer, TOPIC_HISTORY_PROTOCOL)
+                .await
+                .map_err(|error| Error::open_stream(peer, error))?;
+            let request = HistoryRequest {
+                topic: resolved.to_string(),
+            };
+            write_frame(&mut stream, &HISTORY_FORMAT.encode(&request)?).await?;
+            let frame = read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE).await?;
+            HISTORY_FORMAT.decode::<HistoryResponse>(&frame)
+        };
+
+        let response = tokio::time::timeout(DEFAULT_REQUEST_TIMEOUT, exchange)
+            .await
+            .map_err(|_| Error::RequestTimeout {
+                peer,
+                protocol: TOPIC_HISTORY_PROTOCOL.to_string(),
+            })??;
+
+        let mut delivered = 0;
+        for message in response.messages {
+            let envelope = Bytes::from(message.envelope.into_vec());
+            let age = Duration::from_millis(message.age);
+            match self.open(resolved, &envelope).await {
+                Ok(opened) => {
+                    if self.deliver(peer, resolved, envelope, opened, age).await {
+                        delivered += 1;
+                    }
+                }
+                Err(reason) => self.reject(peer, resolved, reason).await,
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// Records a remote peer subscribing to or unsubscribing from a resolved topic, and emits the
+    /// change if there was one
+    async fn track_subscription(&self, peer: PeerId, resolved: &str, subscribed: bool) {
+        let changed = {
+            let mut subscribers = self.subscribers.lock().await;
+            if subscribed {
+                subscribers
+                    .entry(resolved.to_string())
+                    .or_default()
+                    .insert(peer)
+            } else {
+                let removed = subscribers
+                    .get_mut(resolved)
+                    .is_some_and(|peers| peers.remove(&peer));
+                subscribers.retain(|_, peers| !peers.is_empty());
+                removed
+            }
+        };
+        if !changed {
+            return;
+        }
+
+        let identity = self
+            .peers
+            .lock()
+            .await
+            .get(&peer)
+            .map(|(_, identity)| identity.clone());
+        let topic = self.topic_name(resolved).await;
+        self.emit(if subscribed {
+            NodeEvent::PeerSubscribed {
+                peer,
+                identity,
+                topic,
+            }
+        } else {
+            NodeEvent::PeerUnsubscribed {
+                peer,
+                identity,
+                topic,
+            }
+        });
+    }
+
+    /// Drops the subscriptions of a disconnected peer, which the pubsub engines forget silently
+    async fn forget_subscriptions(&self, peer: PeerId) {
+        let topics: Vec<String> = self
+            .subscribers
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, peers)| peers.contains(&peer))
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        for topic in topics {
+            self.track_subscription(peer, &topic, false).await;
+        }
+    }
+
+    /// Reports a received message that failed verification
+    async fn reject(&self, source: PeerId, resolved: &str, reason: String) {
+        tracing::debug!(%source, topic = resolved, "Rejected message: {reason}");
+        self.emit(NodeEvent::RejectedMessage {
+            source,
+            topic: self.topic_name(resolved).await,
+            reason,
+        });
+    }
+
+    /// Tracks a newly opened stream and wraps it in a handle that reports back when dropped
+    async fn register_stream(
+        &self,
+        peer: PeerId,
+        role: StreamRole,
+        protocol: StreamProtocol,
+        stream: Stream,
+    ) -> InterplexStream {
+        let shared = Arc::new(SharedStream::new(stream));
+        let wrapped = InterplexStream::new(
+            peer,
+            role,
+            protocol,
+            shared.clone(),
+            self.dropped_streams.0.clone(),
+        );
+        self.streams
+            .lock()
+            .await
+            .insert(wrapped.id(), (peer, role, shared));
+        self.emit(NodeEvent::StreamOpened {
+            stream_id: wrapped.id(),
+            remote: peer,
+            role,
+        });
+        wrapped
+    }
+
+    /// Wraps streams accepted on a protocol and forwards them to a channel. Dropping the receiver
+    /// stops accepting the protocol.
+    fn forward_incoming(
+        &self,
+        protocol: StreamProtocol,
+        mut incoming: IncomingStreams,
+    ) -> InboundStreams {
+        let (sender, receiver) = mpsc::channel(INCOMING_STREAM_BUFFER);
+        let cself = self.clone();
+        let accepted = protocol.clone();
+        tokio::spawn(async move {
+            let protocol = accepted;
+            loop {
+                select! {
+                    _ = sender.closed() => break,
+                    next = incoming.next() => match next {
+                        Some((peer, stream)) => {
+                            let wrapped = cself
+                                .register_stream(peer, StreamRole::Sink, protocol.clone(), stream)
+                                .await;
+                            if let Err(error) = sender.try_send(wrapped) {
+                                tracing::warn!(%peer, %protocol, "Dropping incoming stream: {error}");
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            }
+        });
+        InboundStreams::new(protocol, receiver)
+    }
+
     async fn handle_swarm_event(&self, event: SwarmEvent<NodeBehaviourEvent>) -> () {
-        let mut swarm = self.swarm.lock().await;
-        let event: Option<Event> = match event {
-            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+        let mut swarm = self.lock_swarm().await;
+        let event: Option<NodeEvent> = match event {
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                num_established,
+                ..
+            } => {
                 if let Some((peer, _)) = self.rendezvous_points.lock().await.get_key_value(&peer_id)
                 {
+                    self.cancel_redial(peer);
                     let _ = swarm.behaviour_mut().rendezvous.register(peer);
+                    if self.config.discovery_interval.is_some() {
+                        self.discover_from(&mut swarm, peer).await;
+
+                        // Watches last until the last connection closes, so one is enough
+                        if num_established.get() == 1 {
+                            swarm.behaviour_mut().rendezvous.watch(
+                                peer,
+                                self.config.discovery_group.clone(),
+                                self.config.discovery_query.clone(),
+                            );
+                        }
+                    }
+                }
+
+                None
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established: 0,
+                ..
+            }
+            | SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                ..
+            } => {
+                if let Some(address) = self.rendezvous_points.lock().await.get(&peer_id) {
+                    if !swarm.is_connected(&peer_id) {
+                        self.schedule_redial(peer_id, address.clone());
+                    }
+                }
+
+                if !swarm.is_connected(&peer_id) {
+                    self.disconnected(&mut swarm, &peer_id).await;
+                    self.forget_subscriptions(peer_id).await;
+                }
+
+                None
+            }
+            SwarmEvent::ExternalAddrConfirmed { .. } => {
+                // Registering fails until an external address is known, so connected rendezvous
+                // points that never accepted a registration are retried here
+                let registered = swarm.behaviour().rendezvous.rendezvous_points();
+                for peer in self.rendezvous_points.lock().await.keys() {
+                    if swarm.is_connected(peer) && !registered.contains(peer) {
+                        let _ = swarm.behaviour_mut().rendezvous.register(peer);
+                    }
                 }
 
                 None
             }
             SwarmEvent::Behaviour(NodeBehaviourEvent::Rendezvous(rdv_event)) => match rdv_event {
                 rendezvous::client::Event::Discovered {
+                    request,
                     peers,
                     rendezvous_node,
                     ..
                 } => {
-                    let mut new_peers: HashMap<PeerId, NodeIdentifier> = HashMap::new();
-                    for peer in peers {
-                        if let Some((ref mut rendezvous_nodes, _)) =
-                            self.peers.lock().await.get_mut(&peer.identity.peer_id)
-                        {
-                            rendezvous_nodes.insert(peer.identity.peer_id.clone());
-                        } else {
-                            let mut nodes: HashSet<PeerId> = HashSet::new();
-                            nodes.insert(rendezvous_node);
-                            self.peers.lock().await.insert(
-                                peer.identity.peer_id.clone(),
-                                (nodes, peer.identity.clone()),
-                            );
-                            new_peers.insert(peer.identity.peer_id.clone(), peer.identity.clone());
-                        }
+                    // Only discoveries of the whole discovery group reveal which peers are gone
+                    let complete = self.discoveries.lock().await.remove(&request);
+                    let listed: Vec<PeerId> =
+                        peers.iter().map(|peer| peer.identity.peer_id).collect();
+                    let (discovered, lost) =
+                        self.apply_discovery(rendezvous_node, peers, complete).await;
+
+                    for identity in lost {
+                        self.unpeer(&mut swarm, &identity.peer_id).await;
+                        self.emit(NodeEvent::LostPeer(identity));
+                    }
+                    self.peer_with(&mut swarm, listed).await;
+
+                    (!discovered.is_empty()).then_some(NodeEvent::DiscoveredPeers(discovered))
+                }
+                rendezvous::client::Event::DiscoverFailed { request, rendezvous_node, error } => {
+                    self.discoveries.lock().await.remove(&request);
+                    tracing::debug!(%rendezvous_node, "Discovery failed: {error:?}");
+                    None
+                }
+                rendezvous::client::Event::RegistrationExpired { rendezvous_node } => {
+                    if swarm.is_connected(&rendezvous_node) {
+                        let _ = swarm.behaviour_mut().rendezvous.register(&rendezvous_node);
                     }
 
-                    Some(Event::DiscoveredPeers(new_peers))
+                    None
+                }
+                rendezvous::client::Event::Joined {
+                    rendezvous_node,
+                    peer,
+                    ..
+                }
+                | rendezvous::client::Event::Updated {
+                    rendezvous_node,
+                    peer,
+                    ..
+                } => {
+                    let listed = peer.identity.peer_id;
+                    let (discovered, _) =
+                        self.apply_discovery(rendezvous_node, vec![peer], false).await;
+                    self.peer_with(&mut swarm, [listed]).await;
+
+                    (!discovered.is_empty()).then_some(NodeEvent::DiscoveredPeers(discovered))
+                }
+                rendezvous::client::Event::Watching {
+                    rendezvous_node,
+                    peers,
+                    ..
+                } => {
+                    let listed: Vec<PeerId> =
+                        peers.iter().map(|peer| peer.identity.peer_id).collect();
+                    let (discovered, _) =
+                        self.apply_discovery(rendezvous_node, peers, false).await;
+                    self.peer_with(&mut swarm, listed).await;
+
+                    (!discovered.is_empty()).then_some(NodeEvent::DiscoveredPeers(discovered))
+                }
+                rendezvous::client::Event::WatchFailed {
+                    rendezvous_node,
+                    error,
+                    ..
+                } => {
+                    tracing::debug!(%rendezvous_node, "Watching peers failed: {error:?}");
+                    None
                 }
                 rendezvous::client::Event::PeerExpired {
                     rendezvous_node,
                     registration,
+                }
+                | rendezvous::client::Event::Left {
+                    rendezvous_node,
+                    peer: registration,
+                    ..
+                }
+                | rendezvous::client::Event::Expired {
+                    rendezvous_node,
+                    peer: registration,
+                    ..
                 } => {
                     let mut locked = self.peers.lock().await;
-                    if let Some((peers, _)) = locked.get(&registration.identity.peer_id).clone() {
+                    if let Some((peers, _)) = locked.get(&registration.identity.peer_id) {
                         if peers.len() == 1 && peers.contains(&rendezvous_node) {
                             locked.remove(&registration.identity.peer_id);
-                            Some(Event::LostPeer(registration.identity.clone()))
+                            self.unpeer(&mut swarm, &registration.identity.peer_id).await;
+                            Some(NodeEvent::LostPeer(registration.identity.clone()))
                         } else {
                             if let Some((ref mut peers, _)) =
                                 locked.get_mut(&registration.identity.peer_id)
@@ -208,143 +1063,207 @@ impl NetworkHandler {
             },
             SwarmEvent::Behaviour(NodeBehaviourEvent::Floodsub(FloodsubEvent::Message(
                 message,
-            ))) => Some(Event::SubscribedMessage {
-                source: message.source.clone(),
-                data: message.data.clone(),
-                topics: message.topics.iter().map(|t| t.id().to_string()).collect(),
-            }),
+            ))) => {
+                drop(swarm);
+                for topic in &message.topics {
+                    match self.open(topic.id(), &message.data).await {
+                        Ok(opened) => {
+                            self.deliver(
+                                message.source,
+                                topic.id(),
+                                message.data.clone(),
+                                opened,
+                                Duration::ZERO,
+                            )
+                            .await;
+                        }
+                        Err(reason) => self.reject(message.source, topic.id(), reason).await,
+                    }
+                }
+                None
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Floodsub(FloodsubEvent::Subscribed {
+                peer_id,
+                topic,
+            })) => {
+                drop(swarm);
+                self.track_subscription(peer_id, topic.id(), true).await;
+                None
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Floodsub(FloodsubEvent::Unsubscribed {
+                peer_id,
+                topic,
+            })) => {
+                drop(swarm);
+                self.track_subscription(peer_id, topic.id(), false).await;
+                None
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Subscribed {
+                peer_id,
+                topic,
+            })) => {
+                drop(swarm);
+                self.track_subscription(peer_id, topic.as_str(), true).await;
+                None
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Unsubscribed {
+                peer_id,
+                topic,
+            })) => {
+                drop(swarm);
+                self.track_subscription(peer_id, topic.as_str(), false).await;
+                None
+            }
+            SwarmEvent::Behaviour(NodeBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                let topic = message.topic.into_string();
+                let envelope = Bytes::from(message.data);
+                let opened = self.open(&topic, &envelope).await;
+                if let Some(gossipsub) = swarm.behaviour_mut().gossipsub.as_mut() {
+                    let acceptance = match opened {
+                        Ok(_) => gossipsub::MessageAcceptance::Accept,
+                        Err(_) => gossipsub::MessageAcceptance::Reject,
+                    };
+                    gossipsub.report_message_validation_result(
+                        &message_id,
+                        &propagation_source,
+                        acceptance,
+                    );
+                }
+                drop(swarm);
+
+                // Anonymous messages have no source, so the peer that relayed them stands in for it
+                let source = message.source.unwrap_or(propagation_source);
+                match opened {
+                    Ok(opened) => {
+                        self.deliver(source, &topic, envelope, opened, Duration::ZERO).await;
+                    }
+                    Err(reason) => self.reject(propagation_source, &topic, reason).await,
+                }
+                None
+            }
             _ => None,
         };
 
         if let Some(evt) = event {
-            let _ = self.events.send(evt).await;
+            self.emit(evt);
         }
     }
 
     async fn handle_command(&self, command: CommandWrapper) -> () {
-        let mut swarm = self.swarm.lock().await;
-        let mut streams = self.streams.lock().await;
-
         let result = match command.command.clone() {
-            Command::OpenStream(peer) => {
-                match swarm
-                    .behaviour()
-                    .stream
-                    .new_control()
-                    .open_stream(peer, StreamProtocol::new("/interplex/streaming"))
+            Command::OpenStream { peer, protocol } => {
+                // Opening a stream requires the swarm to be polled, so the swarm must not be locked here.
+                match self
+                    .control
+                    .clone()
+                    .open_stream(peer, protocol.clone())
                     .await
                 {
-                    Ok(stream) => {
-                        let key = Uuid::new_v4();
-                        streams.insert(
-                            key.clone(),
-                            (
-                                peer.clone(),
-                                StreamRole::Source,
-                                Arc::new(Mutex::new(stream)),
-                            ),
-                        );
-                        let _ = self
-                            .events
-                            .send(Event::StreamOpened {
-                                stream_id: key.clone(),
-                                remote: peer.clone(),
-                                role: StreamRole::Source,
-                            })
-                            .await;
-                        Ok(CommandResponse::OpenStream(key))
-                    }
+                    Ok(stream) => Ok(CommandResponse::OpenStream(
+                        self.register_stream(peer, StreamRole::Source, protocol, stream)
+                            .await,
+                    )),
                     Err(e) => Err(Error::open_stream(peer, e)),
                 }
             }
-            Command::CloseStream(stream_id) => {
-                if let Some((peer, role, stream)) = streams.remove(&stream_id) {
-                    match stream.lock().await.close().await {
-                        Ok(_) => {
-                            let _ = self
-                                .events
-                                .send(Event::StreamClosed {
-                                    stream_id,
-                                    remote: peer,
-                                    role,
-                                })
-                                .await;
-                            Ok(CommandResponse::CloseStream)
-                        }
-                        Err(error) => Err(Error::close_stream(stream_id, peer, error)),
-                    }
-                } else {
-                    Err(Error::unknown_stream(stream_id))
-                }
-            }
-            Command::ReadStream {
-                stream_id,
-                buf_size,
-            } => {
-                if let Some((peer, _, stream)) = streams.get(&stream_id) {
-                    let mut locked = stream.lock().await;
-                    let mut buffer = vec![0u8; buf_size];
-
-                    match locked.read(&mut buffer).await {
-                        Ok(bytes_read) => Ok(CommandResponse::ReadStream {
-                            data: buffer,
-                            bytes_read,
-                        }),
-                        Err(error) => {
-                            Err(Error::read_stream(stream_id, peer.clone(), buf_size, error))
-                        }
-                    }
-                } else {
-                    Err(Error::unknown_stream(stream_id))
-                }
-            }
-            Command::WriteStream { stream_id, data } => {
-                if let Some((peer, _, stream)) = streams.get(&stream_id) {
-                    let mut locked = stream.lock().await;
-
-                    match locked.write_all(&data).await {
-                        Ok(_) => Ok(CommandResponse::WriteStream(data.len())),
-                        Err(error) => Err(Error::write_stream(stream_id, peer.clone(), error)),
-                    }
-                } else {
-                    Err(Error::unknown_stream(stream_id))
-                }
-            }
+            Command::Accept(protocol) => match self.control.clone().accept(protocol.clone()) {
+                Ok(incoming) => Ok(CommandResponse::Accept(
+                    self.forward_incoming(protocol, incoming),
+                )),
+                Err(_) => Err(Error::ProtocolRegistered(protocol.to_string())),
+            },
             Command::Subscribe(topics) => {
+                let mut swarm = self.lock_swarm().await;
                 let mut subs = self.topics.lock().await;
                 for topic in topics {
-                    if swarm.behaviour_mut().floodsub.subscribe(Topic::new(&topic)) {
-                        subs.push(topic);
+                    let resolved = self.resolve_topic(&topic);
+                    if swarm.behaviour_mut().subscribe(&resolved) {
+                        subs.insert(resolved, topic.name().to_string());
                     }
                 }
 
                 Ok(CommandResponse::Subscribe)
             }
             Command::Unsubscribe(topics) => {
+                let mut swarm = self.lock_swarm().await;
                 let mut subs = self.topics.lock().await;
-                for topic in topics.clone() {
-                    if swarm
-                        .behaviour_mut()
-                        .floodsub
-                        .unsubscribe(Topic::new(&topic))
-                    {
-                        *subs = subs
-                            .iter()
-                            .filter_map(|s| {
-                                if topics.contains(s) {
-                                    Some(s.clone())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect();
+                for topic in topics {
+                    let resolved = self.resolve_topic(&topic);
+                    if swarm.behaviour_mut().unsubscribe(&resolved) {
+                        subs.remove(&resolved);
+                        self.history.lock().await.remove(&resolved);
                     }
+
+                    // Dropping the sender ends the streams of the topic
+                    self.topic_streams.lock().await.remove(&resolved);
                 }
 
                 Ok(CommandResponse::Unsubscribe)
             }
+            Command::Publish { topic, data } => {
+                let resolved = self.resolve_topic(&topic);
+                match self.seal(&resolved, data) {
+                    Ok((id, envelope)) => {
+                        // Published messages are kept too, so that the publisher can serve them even
+                        // if no peer received them live
+                        if self.topics.lock().await.contains_key(&resolved) {
+                            self.remember(&resolved, id, envelope.clone(), Duration::ZERO)
+                                .await;
+                        }
+
+                        self.lock_swarm()
+                            .await
+                            .behaviour_mut()
+                            .publish(&resolved, envelope)
+                            .map(|_| CommandResponse::Publish)
+                    }
+                    Err(error) => Err(error),
+                }
+            }
+            Command::Messages(topic) => {
+                let mut swarm = self.lock_swarm().await;
+                let mut subs = self.topics.lock().await;
+                let resolved = self.resolve_topic(&topic);
+                if swarm.behaviour_mut().subscribe(&resolved) {
+                    subs.insert(resolved.clone(), topic.name().to_string());
+                }
+
+                let mut streams = self.topic_streams.lock().await;
+                streams.retain(|_, sender| sender.receiver_count() > 0);
+                let receiver = streams
+                    .entry(resolved)
+                    .or_insert_with(|| broadcast::channel(TOPIC_BUFFER).0)
+                    .subscribe();
+                Ok(CommandResponse::Messages(receiver))
+            }
+            Command::TopicPeers(topic) => {
+                let resolved = self.resolve_topic(&topic);
+                let subscribers = self
+                    .subscribers
+                    .lock()
+                    .await
+                    .get(&resolved)
+                    .cloned()
+                    .unwrap_or_default();
+                let peers = self.peers.lock().await;
+                Ok(CommandResponse::TopicPeers(
+                    subscribers
+                        .iter()
+                        .filter_map(|peer| peers.get(peer).map(|(_, identity)| identity.clone()))
+                        .collect(),
+                ))
+            }
+            Command::PullHistory { topic, peer } => self
+                .pull_history(&self.resolve_topic(&topic), peer)
+                .await
+                .map(CommandResponse::PullHistory),
             Command::ExitLoop => Ok(CommandResponse::ExitLoop),
             Command::AddRendezvous(address) => {
+                let mut swarm = self.lock_swarm().await;
                 let mut rendezvous_points = self.rendezvous_points.lock().await;
                 if let Some(peer) = address
                     .iter()
@@ -357,10 +1276,13 @@ impl NetworkHandler {
                     })
                     .last()
                 {
-                    match swarm.dial(address.clone()) {
-                        Ok(_) => {
-                            rendezvous_points.insert(peer.clone(), address.clone());
-                            Ok(CommandResponse::AddRendezvous(peer.clone()))
+                    match Self::dial_rendezvous(&mut swarm, peer, address.clone()) {
+                        Ok(_) | Err(DialError::DialPeerConditionFalse(_)) => {
+                            if swarm.is_connected(&peer) {
+                                let _ = swarm.behaviour_mut().rendezvous.register(&peer);
+                            }
+                            rendezvous_points.insert(peer, address.clone());
+                            Ok(CommandResponse::AddRendezvous(peer))
                         }
                         Err(error) => Err(Error::connection(
                             "connecting to a new rendezvous node",
@@ -378,42 +1300,145 @@ impl NetworkHandler {
                 }
             }
             Command::RemoveRendezvous(peer_id) => {
+                let mut swarm = self.lock_swarm().await;
                 let mut rendezvous_points = self.rendezvous_points.lock().await;
                 swarm.behaviour_mut().rendezvous.deregister(&peer_id);
                 rendezvous_points.remove(&peer_id);
+                self.cancel_redial(&peer_id);
                 Ok(CommandResponse::RemoveRendezvous)
             }
-            Command::UpdateRemotes(group) => {
-                let rendezvous_points = self.rendezvous_points.lock().await;
-                for peer in rendezvous_points.keys() {
-                    swarm
-                        .behaviour_mut()
-                        .rendezvous
-                        .discover(peer, group.clone());
-                }
-
-                Ok(CommandResponse::UpdateRemotes)
-            },
-            Command::ListPeers => Ok(CommandResponse::ListPeers(self.peers.lock().await.iter().map(|(k, (_, v))| (k.clone(), v.clone())).collect())),
+            Command::RefreshPeers => {
+                self.refresh_peers(&mut *self.lock_swarm().await).await;
+                Ok(CommandResponse::RefreshPeers)
+            }
+            Command::ListPeers => Ok(CommandResponse::ListPeers(self.peers.lock().await.iter().map(|(k, (_, v))| (*k, v.clone())).collect())),
             Command::GetPeer(id) => Ok(CommandResponse::GetPeer(self.peers.lock().await.get(&id).and_then(|(_, node)| Some(node.clone()))))
         };
 
         let _ = command.response_channel.send(result).await;
     }
 
+    /// Deregisters from every rendezvous point, closes all open streams and waits for running
+    /// handlers, abandoning whatever is left once [`SHUTDOWN_TIMEOUT`] has passed
+    async fn shutdown(&self, mut handlers: JoinSet<()>) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        // Dropping the pending redials aborts them, and backoff starts over once the loop restarts
+        *self.reconnects.lock().unwrap() = Reconnects::default();
+
+        let mut deregistering: HashSet<PeerId> = HashSet::new();
+        {
+            let mut swarm = self.swarm.lock().await;
+            for peer in self.rendezvous_points.lock().await.keys() {
+                if swarm.is_connected(peer) {
+                    swarm.behaviour_mut().rendezvous.deregister(peer);
+                    deregistering.insert(*peer);
+                }
+            }
+        }
+
+        while !deregistering.is_empty() {
+            match self.next_swarm_event(deadline).await {
+                Some(SwarmEvent::Behaviour(NodeBehaviourEvent::Rendezvous(
+                    rendezvous::client::Event::Deregistered {
+                        rendezvous_node, ..
+                    }
+                    | rendezvous::client::Event::DeregisterFailed {
+                        rendezvous_node, ..
+                    },
+                ))) => {
+                    deregistering.remove(&rendezvous_node);
+                }
+                Some(_) => (),
+                None => {
+                    tracing::warn!(
+                        "Timed out deregistering from {} rendezvous point(s)",
+                        deregistering.len()
+                    );
+                    break;
+                }
+            }
+        }
+
+        let open: Vec<_> = self.streams.lock().await.drain().collect();
+        join_all(
+            open.into_iter()
+                .map(|(stream_id, (remote, role, shared))| async move {
+                    if let Ok(Err(error)) =
+                        tokio::time::timeout_at(deadline, shared.shutdown()).await
+                    {
+                        tracing::debug!(%stream_id, "Failed to close stream: {error}");
+                    }
+                    self.emit(NodeEvent::StreamClosed {
+                        stream_id,
+                        remote,
+                        role,
+                    });
+                }),
+        )
+        .await;
+
+        // Handlers may still need the swarm to make progress, so it keeps being polled while they finish
+        while !handlers.is_empty() {
+            select! {
+                _ = handlers.join_next() => (),
+                event = self.next_swarm_event(deadline) => if event.is_none() {
+                    tracing::warn!("Aborting {} handler(s) still running at shutdown", handlers.len());
+                    handlers.abort_all();
+                    break;
+                },
+            }
+        }
+    }
+
     async fn event_loop(self) -> CResult<Self> {
         let mut processing_handlers = JoinSet::<()>::new();
+        let mut discovery = self.config.discovery_interval.map(|period| {
+            let mut discovery = interval(period);
+            discovery.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            discovery
+        });
+        // Streams are only accepted once for the handler's lifetime, so the server outlives restarts
+        if let Some(incoming) = self.history_incoming.lock().unwrap().take() {
+            if let Some(policy) = &self.config.topic_history {
+                serve_history(incoming, self.history.clone(), policy.clone());
+            }
+        }
 
-        loop {
+        // Registrations are withdrawn on shutdown, so rendezvous points that stayed connected while
+        // the loop was stopped must be registered with again
+        {
             let mut swarm = self.swarm.lock().await;
-            let mut control = swarm.behaviour().stream.new_control();
-            let mut streams = control
-                .accept(StreamProtocol::new("/interplex/streaming"))
-                .or_else(|e| Err(InterplexError::wrap(e)))?;
-            let next_event: Option<EventType> = select! {
-                event = swarm.select_next_some() => Some(EventType::Swarm(event)),
-                event = self.commands.recv() => if let Ok(ev) = event {Some(EventType::Command(ev))} else {None},
-                event = streams.next() => if let Some((peer, stream)) = event {Some(EventType::Stream(peer, stream))} else {None}
+            for (peer, address) in self.rendezvous_points.lock().await.iter() {
+                if swarm.is_connected(peer) {
+                    let _ = swarm.behaviour_mut().rendezvous.register(peer);
+                } else {
+                    match Self::dial_rendezvous(&mut swarm, *peer, address.clone()) {
+                        Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => (),
+                        Err(error) => {
+                            tracing::warn!(%peer, "Failed to dial rendezvous point: {error}");
+                            self.schedule_redial(*peer, address.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        loop {
+            while processing_handlers.try_join_next().is_some() {}
+
+            // Locks are scoped to the select so that spawned handlers can access the swarm between events
+            let next_event: Option<EventType> = {
+                let mut swarm = self.swarm.lock().await;
+                select! {
+                    event = swarm.select_next_some() => Some(EventType::Swarm(event)),
+                    event = self.commands.recv() => match event {
+                        Ok(ev) => Some(EventType::Command(ev)),
+                        Err(_) => break,
+                    },
+                    event = self.dropped_streams.1.recv() => event.ok().map(EventType::StreamDropped),
+                    _ = self.swarm_wanted.notified() => None,
+                    _ = next_tick(&mut discovery) => Some(EventType::RefreshPeers),
+                }
             };
 
             if let Some(event) = next_event {
@@ -422,6 +1447,7 @@ impl NetworkHandler {
                         response_channel,
                         command: Command::ExitLoop,
                     }) => {
+                        self.shutdown(std::mem::take(&mut processing_handlers)).await;
                         let _ = response_channel.send(Ok(CommandResponse::ExitLoop)).await;
                         response_channel.close();
                         break;
@@ -436,24 +1462,17 @@ impl NetworkHandler {
                         processing_handlers
                             .spawn(async move { cself.handle_swarm_event(event).await });
                     }
-                    EventType::Stream(peer, stream) => {
-                        let cself = self.clone();
-                        processing_handlers.spawn(async move {
-                            let mut own_streams = cself.streams.lock().await;
-                            let key = Uuid::new_v4();
-                            own_streams.insert(
-                                key.clone(),
-                                (peer.clone(), StreamRole::Sink, Arc::new(Mutex::new(stream))),
-                            );
-                            let _ = cself
-                                .events
-                                .send(Event::StreamOpened {
-                                    stream_id: key,
-                                    remote: peer,
-                                    role: StreamRole::Sink,
-                                })
-                                .await;
-                        });
+                    EventType::RefreshPeers => {
+                        self.refresh_peers(&mut *self.swarm.lock().await).await;
+                    }
+                    EventType::StreamDropped(stream_id) => {
+                        if let Some((remote, role, _)) = self.streams.lock().await.remove(&stream_id) {
+                            self.emit(NodeEvent::StreamClosed {
+                                stream_id,
+                                remote,
+                                role,
+                            });
+                        }
                     }
                 }
             }
@@ -466,3 +1485,13 @@ impl NetworkHandler {
         tokio::spawn(async move { self.event_loop().await })
     }
 }
+
+/// Waits for the next tick of an optional interval, never completing without one
+async fn next_tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
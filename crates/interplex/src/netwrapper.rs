@@ -3,55 +3,221 @@ use std::sync::Arc;
 use async_channel::{Receiver, Sender};
 use interplex_common::identification::NodeIdentifier;
 use libp2p::{identity::Keypair, Multiaddr};
-use tokio::{sync::Mutex, task::JoinHandle};
+use tokio::{
+    sync::{broadcast, Mutex},
+    task::JoinHandle,
+};
 
 use crate::{
+    config::NetworkConfig,
     error::CResult,
-    ipc::{CommandWrapper, Event},
+    hooks::Hooks,
+    ipc::{Command, CommandResponse, CommandWrapper, NodeEvent},
     network::NetworkHandler,
     Error,
 };
 
 pub(crate) enum NetworkState {
     Running(JoinHandle<CResult<NetworkHandler>>),
+
+    /// The event loop was asked to exit and is shutting down
+    Stopping,
     Ready(NetworkHandler),
     Failed(Error),
 }
 
+impl NetworkState {
+    fn running(&self) -> bool {
+        matches!(self, NetworkState::Running(handle) if !handle.is_finished())
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct Network {
     state: Arc<Mutex<NetworkState>>,
-    commands: Sender<CommandWrapper>,
-    events: Receiver<Event>,
+    commands: (Sender<CommandWrapper>, Receiver<CommandWrapper>),
+    events: broadcast::Sender<NodeEvent>,
+    hooks: Hooks,
+    identifier: NodeIdentifier,
+    rendezvous_nodes: Vec<Multiaddr>,
+    keypair: Keypair,
+    config: NetworkConfig,
 }
 
+
 impl Network {
     pub fn create(
-        (command_tx, command_rx): (Sender<CommandWrapper>, Receiver<CommandWrapper>),
-        (event_tx, event_rx): (Sender<Event>, Receiver<Event>),
+        commands: (Sender<CommandWrapper>, Receiver<CommandWrapper>),
+        events: broadcast::Sender<NodeEvent>,
+        hooks: Hooks,
         identifier: NodeIdentifier,
         rendezvous_nodes: Vec<Multiaddr>,
         keypair: Keypair,
+        config: NetworkConfig,
     ) -> CResult<Self> {
+        let handler = NetworkHandler::new(
+            commands.1.clone(),
+            events.clone(),
+            hooks.clone(),
+            identifier.clone(),
+            rendezvous_nodes.clone(),
+            keypair.clone(),
+            config.clone(),
+        )?;
+
         Ok(Self {
-            state: Arc::new(Mutex::new(NetworkState::Ready(
-                NetworkHandler::new(command_rx, event_tx, identifier, rendezvous_nodes, keypair)
-                    .or_else(|e| Err(Error::Internal(e)))?,
-            ))),
-            commands: command_tx,
-            events: event_rx,
+            state: Arc::new(Mutex::new(NetworkState::Ready(handler))),
+            commands,
+            events,
+            hooks,
+            identifier,
+            rendezvous_nodes,
+            keypair,
+            config,
         })
     }
 
-    pub fn running(&self) -> bool {
-        if let NetworkState::Running(handle) = self.state.blocking_lock() {
-            !handle.is_finished()
-        } else {
-            false
+    /// Creates a fresh handler, used when recovering from a failed event loop
+    fn build_handler(&self) -> CResult<NetworkHandler> {
+        Ok(NetworkHandler::new(
+            self.commands.1.clone(),
+            self.events.clone(),
+            self.hooks.clone(),
+            self.identifier.clone(),
+            self.rendezvous_nodes.clone(),
+            self.keypair.clone(),
+            self.config.clone(),
+        )?)
+    }
+
+    /// Creates a new receiver for all events emitted after this call
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn running(&self) -> bool {
+        self.state.lock().await.running()
+    }
+
+    /// The error that caused the event loop to fail, if it has
+    pub async fn failure(&self) -> Option<Error> {
+        match &*self.state.lock().await {
+            NetworkState::Failed(error) => Some(error.clone()),
+            _ => None,
+        }
+    }
+
+    /// Spawns the event loop. A failed network is rebuilt from scratch before starting.
+    pub async fn start(&self) -> CResult<()> {
+        let mut state = self.state.lock().await;
+        let previous = std::mem::replace(&mut *state, NetworkState::Failed(Error::NetworkOffline));
+        let handler = match previous {
+            NetworkState::Ready(handler) => Ok(handler),
+            NetworkState::Running(handle) if !handle.is_finished() => {
+                *state = NetworkState::Running(handle);
+                return Err(Error::NetworkRunning);
+            }
+            NetworkState::Stopping => {
+                *state = NetworkState::Stopping;
+                return Err(Error::NetworkStopping);
+            }
+            NetworkState::Running(handle) => match handle.await {
+                Ok(Ok(handler)) => Ok(handler),
+                _ => self.build_handler(),
+            },
+            NetworkState::Failed(_) => self.build_handler(),
+        };
+
+        match handler {
+            Ok(handler) => {
+                self.reject_queued();
+                *state = NetworkState::Running(handler.start_event_loop());
+                Ok(())
+            }
+            Err(error) => {
+                *state = NetworkState::Failed(error.clone());
+                Err(error)
+            }
+        }
+    }
+
+    /// Requests that the event loop exit and reclaims its handler so the network can be restarted.
+    ///
+    /// The state is not locked while the event loop shuts down, so commands sent in the meantime
+    /// fail right away instead of waiting for it. Commands that were still queued once it has
+    /// exited are answered with [`Error::NetworkOffline`].
+    pub async fn stop(&self) -> CResult<()> {
+        let handle = {
+            let mut state = self.state.lock().await;
+            match std::mem::replace(&mut *state, NetworkState::Stopping) {
+                NetworkState::Running(handle) => {
+                    if !handle.is_finished() {
+                        // Commands are queued with the state locked, so none can follow this one
+                        let _ = self.enqueue(Command::ExitLoop).await;
+                    }
+                    handle
+                }
+                other => {
+                    *state = other;
+                    return Err(Error::NetworkOffline);
+                }
+            }
+        };
+
+        let (next, result) = match handle.await {
+            Ok(Ok(handler)) => (NetworkState::Ready(handler), Ok(())),
+            Ok(Err(error)) => (NetworkState::Failed(error.clone()), Err(error)),
+            Err(error) => {
+                let error = Error::connection("joining the network event loop", error);
+                (NetworkState::Failed(error.clone()), Err(error))
+            }
+        };
+        self.reject_queued();
+        *self.state.lock().await = next;
+        result
+    }
+
+    pub async fn restart(&self) -> CResult<()> {
+        // A stop failure leaves the network in the Failed state, which start() rebuilds from.
+        let _ = self.stop().await;
+        self.start().await
+    }
+
+    /// Queues a command for the event loop without checking whether it is running, returning the
+    /// channel its response is sent on
+    async fn enqueue(&self, command: Command) -> CResult<Receiver<CResult<CommandResponse>>> {
+        let (response_tx, response_rx) = async_channel::bounded(1);
+        self.commands
+            .0
+            .send(CommandWrapper {
+                response_channel: response_tx,
+                command,
+            })
+            .await
+            .map_err(|_| Error::NetworkOffline)?;
+        Ok(response_rx)
+    }
+
+    /// Answers the commands left in the queue by an event loop that is no longer running, which
+    /// would otherwise wait for the next one to run them
+    fn reject_queued(&self) {
+        while let Ok(command) = self.commands.1.try_recv() {
+            let _ = command.response_channel.try_send(Err(Error::NetworkOffline));
         }
     }
 
-    pub fn start(&mut self) -> CResult<()> {
-        Ok(())
+    pub async fn command(&self, command: Command) -> CResult<CommandResponse> {
+        let response = {
+            let state = self.state.lock().await;
+            if !state.running() {
+                return Err(match *state {
+                    NetworkState::Stopping => Error::NetworkStopping,
+                    _ => Error::NetworkOffline,
+                });
+            }
+            self.enqueue(command).await?
+        };
+
+        response.recv().await.map_err(|_| Error::NetworkOffline)?
     }
 }
@@ -1,30 +1,44 @@
 use std::{
     collections::HashMap,
+    future::Future,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use async_channel::{Receiver, Sender};
-use interplex_common::identification::{Discoverability, NodeIdentifier};
+use interplex_common::{
+    identification::{Discoverability, NodeIdentifier},
+    rendezvous::query::Query,
+};
 use libp2p::{
+    futures::{stream, Stream},
     identity::{Keypair, PublicKey},
     multiaddr::Protocol,
     Multiaddr, PeerId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_cbor::{value::to_value, Value};
+use tokio::sync::broadcast::{self, error::RecvError};
 
 use crate::{
+    config::{
+        GossipsubSettings, HistoryPolicy, NetworkConfig, PubsubEngine, ReconnectPolicy, TopicScope,
+    },
     error::{CResult, Error},
-    ipc::{CommandWrapper, Event}
+    hooks::{EventFilter, HookHandle, HookOutput, Hooks},
+    ipc::{Command, CommandResponse, CommandWrapper, NodeEvent},
+    netwrapper::Network,
+    stream::{parse_protocol, InboundStreams, InterplexStream},
 };
 
+/// Number of events buffered for each subscriber of [`InterplexNode::events`]
+pub const EVENT_BUFFER: usize = 1024;
+
+#[allow(dead_code)]
 #[derive(Clone)]
 pub struct InterplexNode {
-    commands: (Sender<CommandWrapper>, Receiver<CommandWrapper>),
-    events: (Sender<Event>, Receiver<Event>),
     identifier: Arc<Mutex<NodeIdentifier>>,
-    running_network: Arc<Mutex<Network>>,
-    event_hooks: Arc<Mutex<HashMap<String, fn(Event) -> ()>>>,
+    pub(crate) network: Network,
+    hooks: Hooks,
     keypair: Keypair,
     rendezvous_nodes: Arc<Mutex<HashMap<PeerId, Multiaddr>>>,
 }
@@ -41,6 +55,9 @@ pub struct NodeBuilder {
     keypair: Option<SavedKey>,
     discoverability: Discoverability,
     rendezvous_nodes: HashMap<PeerId, Multiaddr>,
+
+    #[serde(default)]
+    network: NetworkConfig,
 }
 
 impl NodeBuilder {
@@ -101,6 +118,70 @@ impl NodeBuilder {
         }
     }
 
+    /// Sets how rendezvous points are redialed after their connection drops
+    pub fn reconnect_policy(&mut self, policy: ReconnectPolicy) -> &mut Self {
+        self.network.reconnect = policy;
+        self
+    }
+
+    /// Selects the protocol used to publish and receive topic messages (gossipsub by default)
+    pub fn pubsub_engine(&mut self, engine: PubsubEngine) -> &mut Self {
+        self.network.pubsub = engine;
+        self
+    }
+
+    /// Sets the scope of topics given as plain names (namespace by default). Scopes may also be
+    /// chosen per topic, see [`crate::TopicName`].
+    pub fn topic_scope(&mut self, scope: TopicScope) -> &mut Self {
+        self.network.topic_scope = scope;
+        self
+    }
+
+    /// Sets the mesh, message ID and validation settings used when the engine is gossipsub
+    pub fn gossipsub(&mut self, settings: GossipsubSettings) -> &mut Self {
+        self.network.gossipsub = settings;
+        self
+    }
+
+    /// Sets how many discovered peers are connected to for pubsub (32 by default)
+    pub fn peer_budget(&mut self, budget: usize) -> &mut Self {
+        self.network.peer_budget = budget;
+        self
+    }
+
+    /// Keeps recent messages of subscribed topics, and serves them to peers that subscribe later
+    pub fn topic_history(&mut self, policy: HistoryPolicy) -> &mut Self {
+        self.network.topic_history = Some(policy);
+        self
+    }
+
+    /// Asks rendezvous points for peers on an interval, as well as whenever one is connected.
+    /// Connected rendezvous points are also watched, so peers joining or leaving in between are
+    /// noticed right away.
+    pub fn discovery_interval(&mut self, interval: Duration) -> &mut Self {
+        self.network.discovery_interval = Some(interval);
+        self
+    }
+
+    /// Limits discovery to the peers of one group, rather than the whole namespace
+    pub fn discovery_group(&mut self, group: impl Into<String>) -> &mut Self {
+        self.network.discovery_group = Some(group.into());
+        self
+    }
+
+    /// Limits discovery to the peers whose alias and metadata match a query
+    pub fn discovery_query(&mut self, query: Query) -> &mut Self {
+        self.network.discovery_query = Some(query);
+        self
+    }
+
+    /// Asks rendezvous points to keep this node's registration for the given lifetime, rather
+    /// than the longest they allow. Registrations are refreshed before the granted lifetime ends.
+    pub fn registration_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.network.registration_ttl = Some(ttl);
+        self
+    }
+
     pub fn build(self) -> CResult<InterplexNode> {
         if self.namespace.is_none() {
             return Err(Error::build_node("Namespace must be specified"));
@@ -112,7 +193,7 @@ impl NodeBuilder {
             SavedKey::new().keypair()
         };
 
-        Ok(InterplexNode::new(
+        InterplexNode::new(
             NodeIdentifier {
                 peer_id: key.public().to_peer_id(),
                 namespace: self.namespace.unwrap(),
@@ -123,7 +204,8 @@ impl NodeBuilder {
             },
             key,
             self.rendezvous_nodes,
-        ))
+            self.network,
+        )
     }
 }
 
@@ -140,7 +222,9 @@ impl SavedKey {
 
     pub fn keypair(&self) -> Keypair {
         let mut encoded = self.0.clone();
-        Keypair::ed25519_from_bytes(&mut encoded).unwrap()
+        libp2p::identity::ed25519::Keypair::try_from_bytes(&mut encoded)
+            .unwrap()
+            .into()
     }
 
     pub fn public(&self) -> PublicKey {
@@ -175,19 +259,135 @@ impl InterplexNode {
         identifier: NodeIdentifier,
         keypair: Keypair,
         rendezvous_nodes: HashMap<PeerId, Multiaddr>,
-    ) -> Self {
-        Self {
-            commands: async_channel::unbounded::<CommandWrapper>(),
-            events: async_channel::unbounded::<Event>(),
+        config: NetworkConfig,
+    ) -> CResult<Self> {
+        let hooks = Hooks::default();
+        let network = Network::create(
+            async_channel::unbounded::<CommandWrapper>(),
+            broadcast::channel::<NodeEvent>(EVENT_BUFFER).0,
+            hooks.clone(),
+            identifier.clone(),
+            rendezvous_nodes.values().cloned().collect(),
+            keypair.clone(),
+            config,
+        )?;
+
+        Ok(Self {
             identifier: Arc::new(Mutex::new(identifier)),
-            running_network: Network::Stopped(Arc::new),
-            event_hooks: Arc::new(Mutex::new(HashMap::new())),
+            network,
+            hooks,
             keypair,
             rendezvous_nodes: Arc::new(Mutex::new(rendezvous_nodes)),
+        })
+    }
+
+    pub fn identifier(&self) -> NodeIdentifier {
+        self.identifier.lock().unwrap().clone()
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.keypair.public().to_peer_id()
+    }
+
+    /// Returns a stream of all events emitted by the node after this call.
+    ///
+    /// Each call creates an independent subscriber, so multiple consumers may watch the same events.
+    /// A subscriber that falls more than [`EVENT_BUFFER`] events behind skips the events it missed.
+    pub fn events(&self) -> impl Stream<Item = NodeEvent> + Send + 'static {
+        stream::unfold(self.network.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    /// Registers an async hook, called for every event matching the filter while the network runs.
+    ///
+    /// Hooks may return `()` or `Result<(), E>`. Each call runs as its own task, so a hook that
+    /// fails or panics is logged without affecting the network or other hooks. The hook stays
+    /// registered until the returned handle is dropped or unregistered.
+    pub fn on<F, Fut>(&self, filter: EventFilter, hook: F) -> HookHandle
+    where
+        F: Fn(NodeEvent) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: HookOutput,
+    {
+        self.hooks.register(filter, hook)
+    }
+
+    /// Opens a new stream to a remote peer on a protocol, connecting to the peer if necessary.
+    ///
+    /// The remote must be accepting the protocol (see [`InterplexNode::accept_protocol`]).
+    pub async fn open_stream(
+        &self,
+        peer: PeerId,
+        protocol: impl Into<String>,
+    ) -> CResult<InterplexStream> {
+        let protocol = parse_protocol(protocol)?;
+        match self
+            .network
+            .command(Command::OpenStream { peer, protocol })
+            .await?
+        {
+            CommandResponse::OpenStream(stream) => Ok(stream),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Starts accepting streams opened by remote peers on a protocol (ie `/myapp/sync/1`).
+    ///
+    /// Incoming streams are routed to the [`InboundStreams`] of their protocol, which stops
+    /// accepting the protocol when dropped. Each protocol may only be accepted once at a time,
+    /// and the network must be running.
+    pub async fn accept_protocol(&self, protocol: impl Into<String>) -> CResult<InboundStreams> {
+        let protocol = parse_protocol(protocol)?;
+        match self.network.command(Command::Accept(protocol)).await? {
+            CommandResponse::Accept(incoming) => Ok(incoming),
+            other => Err(Error::unexpected_response(other)),
         }
     }
 
-    pub fn activate_network(&mut self) {
-        if let Ok(node_id) = self.identifier.lock() {}
+    /// Asks every connected rendezvous point for peers right away, without waiting for the
+    /// discovery interval.
+    ///
+    /// Results arrive as [`NodeEvent::DiscoveredPeers`] and [`NodeEvent::LostPeer`] events.
+    pub async fn refresh_peers(&self) -> CResult<()> {
+        match self.network.command(Command::RefreshPeers).await? {
+            CommandResponse::RefreshPeers => Ok(()),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Whether the network event loop is currently running
+    pub async fn running(&self) -> bool {
+        self.network.running().await
+    }
+
+    /// The error that stopped the network event loop, if it failed
+    pub async fn failure(&self) -> Option<Error> {
+        self.network.failure().await
+    }
+
+    /// Starts the network event loop. Fails if the network is already running or still stopping.
+    pub async fn start(&self) -> CResult<()> {
+        self.network.start().await
+    }
+
+    /// Stops the network event loop, keeping its state so that it may be started again.
+    ///
+    /// The node deregisters from its rendezvous points, closes every open stream and waits for
+    /// in-flight commands before returning. Registration is restored when the node is restarted.
+    /// Commands sent while it is stopping fail with [`Error::NetworkStopping`].
+    pub async fn stop(&self) -> CResult<()> {
+        self.network.stop().await
+    }
+
+    /// Stops the network (if running) and starts it again
+    pub async fn restart(&self) -> CResult<()> {
+        self.network.restart().await
     }
 }
@@ -0,0 +1,424 @@
+use std::{
+    fmt::{Debug, Display},
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use libp2p::{
+    bytes::Bytes,
+    futures::{stream::BoxStream, Stream, StreamExt as _},
+    PeerId,
+};
+use interplex_common::identification::NodeIdentifier;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    codec::Format,
+    config::TopicScope,
+    envelope::SenderIdentity,
+    error::{CResult, Error},
+    ipc::{Command, CommandResponse},
+    InterplexNode,
+};
+
+/// Number of messages buffered for each [`TopicMessages`] stream
+pub const TOPIC_BUFFER: usize = 256;
+
+/// A topic name, along with the scope that decides which nodes share it.
+///
+/// Strings convert into names in the node's default scope (see [`crate::NodeBuilder::topic_scope`]),
+/// so `"chat"` only reaches nodes in the same namespace unless configured otherwise. Use
+/// [`TopicName::global`] for topics shared across namespaces.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TopicName {
+    name: String,
+    scope: Option<TopicScope>,
+}
+
+impl TopicName {
+    /// A topic in the node's default scope
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            scope: None,
+        }
+    }
+
+    pub fn scoped(name: impl Into<String>, scope: TopicScope) -> Self {
+        Self {
+            name: name.into(),
+            scope: Some(scope),
+        }
+    }
+
+    /// A topic shared with every node, regardless of namespace or group
+    pub fn global(name: impl Into<String>) -> Self {
+        Self::scoped(name, TopicScope::Global)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The explicit scope of the topic, if it has one
+    pub fn scope(&self) -> Option<TopicScope> {
+        self.scope
+    }
+
+    /// Name of the topic on the network, prefixed according to its scope. Each scope has its own
+    /// marker, and slashes in the namespace and group are escaped, so that names of different
+    /// scopes never resolve to the same topic.
+    pub(crate) fn resolve(&self, default: TopicScope, identifier: &NodeIdentifier) -> String {
+        match self.scope.unwrap_or(default) {
+            TopicScope::Namespace => format!(
+                "interplex/{}/n/{}",
+                escape_segment(&identifier.namespace),
+                self.name
+            ),
+            TopicScope::Group => format!(
+                "interplex/{}/g/{}/{}",
+                escape_segment(&identifier.namespace),
+                escape_segment(&identifier.group()),
+                self.name
+            ),
+            TopicScope::Global => format!("global/{}", self.name),
+        }
+    }
+}
+
+/// Whether a sender may publish on a resolved topic. Namespace and group topics only carry the
+/// messages of nodes in their namespace and group, while global topics carry those of any node.
+pub(crate) fn in_scope(resolved: &str, sender: &SenderIdentity) -> bool {
+    if resolved.starts_with("global/") {
+        return true;
+    }
+
+    let namespace = format!("interplex/{}/", escape_segment(&sender.namespace));
+    match resolved.strip_prefix(&namespace) {
+        Some(rest) if rest.starts_with("n/") => true,
+        Some(rest) => rest.starts_with(&format!("g/{}/", escape_segment(&sender.group()))),
+        None => false,
+    }
+}
+
+/// Escapes a topic prefix segment so that it can't contain a separator
+fn escape_segment(segment: &str) -> String {
+    segment.replace('%', "%25").replace('/', "%2F")
+}
+
+impl From<&str> for TopicName {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<String> for TopicName {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Display for TopicName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// A message received on a subscribed topic. The topic is given without its scope prefix.
+///
+/// `sender` is the identity the message was signed with, and is missing only for messages
+/// published anonymously. Only its peer ID is proven by the signature; see [`SenderIdentity`] for
+/// how the rest is checked. `seqno` increases with every message published by the same node.
+#[derive(Clone, Debug)]
+pub struct TopicMessage {
+    pub source: PeerId,
+    pub sender: Option<SenderIdentity>,
+    pub seqno: u64,
+    pub topic: String,
+    pub data: Bytes,
+}
+
+/// Messages received on a single topic, returned by [`InterplexNode::messages`].
+///
+/// A stream that falls more than [`TOPIC_BUFFER`] messages behind skips the messages it missed.
+/// The stream ends when the node unsubscribes from the topic.
+pub struct TopicMessages {
+    topic: TopicName,
+    inner: BoxStream<'static, TopicMessage>,
+}
+
+impl TopicMessages {
+    pub(crate) fn new(topic: TopicName, receiver: broadcast::Receiver<TopicMessage>) -> Self {
+        let inner = libp2p::futures::stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => return Some((message, receiver)),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .boxed();
+
+        Self { topic, inner }
+    }
+
+    pub fn topic(&self) -> &TopicName {
+        &self.topic
+    }
+}
+
+impl Debug for TopicMessages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopicMessages")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl Stream for TopicMessages {
+    type Item = TopicMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// A topic carrying typed messages, returned by [`InterplexNode::topic`].
+///
+/// Messages are serialized with the chosen [`Format`], which must match between publishers and
+/// subscribers. Received messages are yielded as a [`Stream`] of `(source, message)` pairs. A
+/// message that fails to decode yields [`Error::InvalidMessage`] without ending the stream.
+pub struct TypedTopic<T> {
+    // Boxed to keep the topic Unpin: the command channel held by the node handle is not
+    node: Box<InterplexNode>,
+    format: Format,
+    messages: TopicMessages,
+    _message: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedTopic<T> {
+    /// Sets the serialization format of published and received messages
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        self.messages.topic().name()
+    }
+
+    pub fn topic(&self) -> &TopicName {
+        self.messages.topic()
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl<T: Serialize> TypedTopic<T> {
+    pub async fn publish(&self, message: &T) -> CResult<()> {
+        let data = self.format.encode(message)?;
+        self.node.publish(self.topic().clone(), data).await
+    }
+}
+
+impl<T> Debug for TypedTopic<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedTopic")
+            .field("name", &self.name())
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl<T: DeserializeOwned> Stream for TypedTopic<T> {
+    type Item = CResult<(PeerId, T)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let format = this.format;
+        this.messages.poll_next_unpin(cx).map(|message| {
+            message.map(|message| {
+                format
+                    .decode::<T>(&message.data)
+                    .map(|decoded| (message.source, decoded))
+                    .map_err(|error| Error::InvalidMessage {
+                        topic: message.topic,
+                        peer: message.source,
+                        reason: error.to_string(),
+                    })
+            })
+        })
+    }
+}
+
+impl InterplexNode {
+    /// Publishes a message to every peer subscribed to `topic`. The node does not need to be
+    /// subscribed to the topic itself.
+    pub async fn publish(&self, topic: impl Into<TopicName>, data: impl Into<Bytes>) -> CResult<()> {
+        match self
+            .network
+            .command(Command::Publish {
+                topic: topic.into(),
+                data: data.into(),
+            })
+            .await?
+        {
+            CommandResponse::Publish => Ok(()),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Subscribes to topics. Messages arrive as [`crate::NodeEvent::SubscribedMessage`] events,
+    /// and through [`InterplexNode::messages`].
+    pub async fn subscribe<T: Into<TopicName>>(
+        &self,
+        topics: impl IntoIterator<Item = T>,
+    ) -> CResult<()> {
+        let topics = topics.into_iter().map(Into::into).collect();
+        match self.network.command(Command::Subscribe(topics)).await? {
+            CommandResponse::Subscribe => Ok(()),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Unsubscribes from topics. Every [`TopicMessages`] stream and [`TypedTopic`] of these
+    /// topics ends, and yields `None` from then on.
+    pub async fn unsubscribe<T: Into<TopicName>>(
+        &self,
+        topics: impl IntoIterator<Item = T>,
+    ) -> CResult<()> {
+        let topics = topics.into_iter().map(Into::into).collect();
+        match self.network.command(Command::Unsubscribe(topics)).await? {
+            CommandResponse::Unsubscribe => Ok(()),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Lists the discovered peers that are subscribed to a topic, as far as the peers the node is
+    /// connected to have announced. Subscribers that weren't discovered through a rendezvous point
+    /// are left out; [`crate::NodeEvent::PeerSubscribed`] events report them as well.
+    pub async fn topic_peers(&self, topic: impl Into<TopicName>) -> CResult<Vec<NodeIdentifier>> {
+        match self.network.command(Command::TopicPeers(topic.into())).await? {
+            CommandResponse::TopicPeers(peers) => Ok(peers),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Subscribes to a topic if needed, and returns a stream of the messages received on it from
+    /// now on.
+    ///
+    /// Any number of streams may be open for the same topic. Dropping a stream does not
+    /// unsubscribe from the topic, but unsubscribing ends every stream of the topic.
+    pub async fn messages(&self, topic: impl Into<TopicName>) -> CResult<TopicMessages> {
+        let topic: TopicName = topic.into();
+        match self.network.command(Command::Messages(topic.clone())).await? {
+            CommandResponse::Messages(receiver) => Ok(TopicMessages::new(topic, receiver)),
+            other => Err(Error::unexpected_response(other)),
+        }
+    }
+
+    /// Subscribes to a topic if needed, and returns a handle to publish and receive typed
+    /// messages on it. Messages are encoded as MessagePack unless another [`Format`] is chosen
+    /// with [`TypedTopic::with_format`].
+    pub async fn topic<T>(&self, name: impl Into<TopicName>) -> CResult<TypedTopic<T>> {
+        Ok(TypedTopic {
+            node: Box::new(self.clone()),
+            format: Format::default(),
+            messages: self.messages(name).await?,
+            _message: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use interplex_common::identification::NodeBuilder;
+
+    use super::*;
+
+    fn identifier(namespace: &str, group: Option<&str>) -> NodeIdentifier {
+        let mut builder = NodeBuilder::new(namespace);
+        if let Some(group) = group {
+            builder.group(group);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn resolves_each_scope() {
+        let node = identifier("ns", Some("team"));
+        let resolve = |topic: TopicName| topic.resolve(TopicScope::Namespace, &node);
+
+        assert_eq!(resolve("chat".into()), "interplex/ns/n/chat");
+        assert_eq!(
+            resolve(TopicName::scoped("chat", TopicScope::Group)),
+            "interplex/ns/g/team/chat"
+        );
+        assert_eq!(resolve(TopicName::global("chat")), "global/chat");
+        assert_eq!(
+            TopicName::new("chat").resolve(TopicScope::Group, &node),
+            "interplex/ns/g/team/chat"
+        );
+    }
+
+    #[test]
+    fn scopes_never_collide() {
+        let node = identifier("ns", None);
+        let namespaced = TopicName::new("default/chat").resolve(TopicScope::Namespace, &node);
+        let grouped = TopicName::new("chat").resolve(TopicScope::Group, &node);
+        assert_ne!(namespaced, grouped);
+
+        let global = TopicName::global("interplex/ns/n/x").resolve(TopicScope::Namespace, &node);
+        let namespaced = TopicName::new("x").resolve(TopicScope::Namespace, &node);
+        assert_ne!(global, namespaced);
+
+        // Separators in the group can't pass for part of the topic name
+        let slashed = TopicName::new("c").resolve(TopicScope::Group, &identifier("ns", Some("a/b")));
+        let nested = TopicName::new("b/c").resolve(TopicScope::Group, &identifier("ns", Some("a")));
+        assert_ne!(slashed, nested);
+    }
+
+    #[test]
+    fn senders_must_be_in_scope() {
+        let node = identifier("ns", Some("team"));
+        let resolve = |scope| TopicName::scoped("chat", scope).resolve(TopicScope::Namespace, &node);
+        let sender = |namespace: &str, group: Option<&str>| {
+            SenderIdentity::from(&identifier(namespace, group))
+        };
+
+        let namespace = resolve(TopicScope::Namespace);
+        assert!(in_scope(&namespace, &sender("ns", None)));
+        assert!(!in_scope(&namespace, &sender("other", None)));
+
+        let group = resolve(TopicScope::Group);
+        assert!(in_scope(&group, &sender("ns", Some("team"))));
+        assert!(!in_scope(&group, &sender("ns", Some("other"))));
+        assert!(!in_scope(&group, &sender("other", Some("team"))));
+
+        let global = resolve(TopicScope::Global);
+        assert!(in_scope(&global, &sender("other", Some("other"))));
+    }
+
+    #[tokio::test]
+    async fn messages_end_when_the_topic_is_dropped() {
+        let (sender, receiver) = broadcast::channel(TOPIC_BUFFER);
+        let mut messages = TopicMessages::new("chat".into(), receiver);
+        let message = TopicMessage {
+            source: PeerId::random(),
+            sender: None,
+            seqno: 0,
+            topic: "chat".into(),
+            data: Bytes::from_static(b"data"),
+        };
+
+        sender.send(message).unwrap();
+        drop(sender);
+        assert!(messages.next().await.is_some());
+        assert!(messages.next().await.is_none());
+    }
+}
@@ -0,0 +1,364 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use libp2p::{
+    futures::{AsyncRead, AsyncWrite, AsyncWriteExt as _, StreamExt as _},
+    PeerId, StreamProtocol,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error as ErrorDe;
+use tokio::task::JoinHandle;
+
+use crate::{
+    codec::{read_frame, write_frame, Format, DEFAULT_MAX_FRAME_SIZE},
+    error::{CResult, Error},
+    stream::{parse_protocol, InterplexStream},
+    InterplexNode,
+};
+
+/// Timeout applied by [`InterplexNode::request`]
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serialization format of requests and responses
+const RPC_FORMAT: Format = Format::MessagePack;
+
+/// Why a remote peer failed to answer a request
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RemoteErrorKind {
+    /// The request could not be decoded into the type the remote serves
+    InvalidRequest,
+
+    /// The remote handler returned an error
+    Handler,
+}
+
+/// An error reported by a remote peer while serving a request
+#[derive(Clone, Debug, ErrorDe, Serialize, Deserialize)]
+#[error("{kind:?}: {message}")]
+pub struct RemoteError {
+    pub kind: RemoteErrorKind,
+    pub message: String,
+}
+
+/// Keeps a service registered with [`InterplexNode::serve`]. The service stops when this handle is
+/// dropped or stopped.
+pub struct ServiceHandle {
+    protocol: StreamProtocol,
+    task: JoinHandle<()>,
+}
+
+impl ServiceHandle {
+    pub fn protocol(&self) -> StreamProtocol {
+        self.protocol.clone()
+    }
+
+    pub fn stop(self) {
+        drop(self)
+    }
+}
+
+impl Drop for ServiceHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answers a single request from `peer`. Every request is sent on its own stream, which is closed
+/// afterwards.
+async fn respond<S, Req, Resp, Fut, E>(
+    mut stream: S,
+    peer: PeerId,
+    protocol: StreamProtocol,
+    handler: impl Fn(PeerId, Req) -> Fut,
+) -> CResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    Fut: Future<Output = Result<Resp, E>>,
+    E: Display,
+{
+    let frame = tokio::time::timeout(
+        DEFAULT_REQUEST_TIMEOUT,
+        read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE),
+    )
+    .await
+    .map_err(|_| Error::RequestTimeout {
+        peer,
+        protocol: protocol.to_string(),
+    })??;
+
+    let response: Result<Resp, RemoteError> = match RPC_FORMAT.decode::<Req>(&frame) {
+        Ok(request) => handler(peer, request)
+            .await
+            .map_err(|e| RemoteError {
+                kind: RemoteErrorKind::Handler,
+                message: e.to_string(),
+            }),
+        Err(e) => Err(RemoteError {
+            kind: RemoteErrorKind::InvalidRequest,
+            message: e.to_string(),
+        }),
+    };
+
+    write_frame(&mut stream, &RPC_FORMAT.encode(&response)?).await?;
+    stream.close().await?;
+    Ok(())
+}
+
+/// Sends an encoded request on the stream returned by `open` and decodes the response, failing if
+/// the whole exchange takes longer than `timeout`
+async fn exchange<S, Resp>(
+    open: impl Future<Output = CResult<S>>,
+    peer: PeerId,
+    protocol: StreamProtocol,
+    payload: &[u8],
+    timeout: Duration,
+) -> CResult<Resp>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Resp: DeserializeOwned,
+{
+    let exchange = async {
+        let mut stream = open.await?;
+        write_frame(&mut stream, payload).await?;
+        let frame = read_frame(&mut stream, DEFAULT_MAX_FRAME_SIZE).await?;
+        RPC_FORMAT
+            .decode::<Result<Resp, RemoteError>>(&frame)?
+            .map_err(|error| Error::Remote { peer, error })
+    };
+
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| Error::RequestTimeout {
+            peer,
+            protocol: protocol.to_string(),
+        })?
+}
+
+impl InterplexNode {
+    /// Sends a request to a peer serving `protocol` and waits for its response, failing after
+    /// [`DEFAULT_REQUEST_TIMEOUT`].
+    pub async fn request<Req, Resp>(
+        &self,
+        peer: PeerId,
+        protocol: impl Into<String>,
+        request: Req,
+    ) -> CResult<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        self.request_with_timeout(peer, protocol, request, DEFAULT_REQUEST_TIMEOUT)
+            .await
+    }
+
+    /// Sends a request to a peer serving `protocol` and waits up to `timeout` for its response.
+    ///
+    /// Each request uses its own stream, so any number of requests may be in flight to the same
+    /// peer at once. Errors raised by the remote handler are returned as [`Error::Remote`].
+    pub async fn request_with_timeout<Req, Resp>(
+        &self,
+        peer: PeerId,
+        protocol: impl Into<String>,
+        request: Req,
+        timeout: Duration,
+    ) -> CResult<Resp>
+    where
+        Req: Serialize,
+        Resp: DeserializeOwned,
+    {
+        let protocol = parse_protocol(protocol)?;
+        let payload = RPC_FORMAT.encode(&request)?;
+        let open = self.open_stream(peer, protocol.to_string());
+        exchange::<InterplexStream, _>(open, peer, protocol, &payload, timeout).await
+    }
+
+    /// Serves requests sent with [`InterplexNode::request`] on `protocol`.
+    ///
+    /// Each request is handled in its own task. Handler errors are sent back to the requester as
+    /// a [`RemoteError`]. The network must be running, and requests are served until the returned
+    /// handle is dropped.
+    pub async fn serve<Req, Resp, Fut, E>(
+        &self,
+        protocol: impl Into<String>,
+        handler: impl Fn(PeerId, Req) -> Fut + Clone + Send + Sync + 'static,
+    ) -> CResult<ServiceHandle>
+    where
+        Req: DeserializeOwned + Send + 'static,
+        Resp: Serialize + Send + 'static,
+        Fut: Future<Output = Result<Resp, E>> + Send + 'static,
+        E: Display + Send + 'static,
+    {
+        let mut incoming = self.accept_protocol(protocol).await?;
+        let protocol = incoming.protocol();
+        let task = tokio::spawn(async move {
+            while let Some(stream) = incoming.next().await {
+                let handler = handler.clone();
+                let (peer, protocol) = (stream.peer(), stream.protocol());
+                tokio::spawn(async move {
+                    if let Err(error) = respond(stream, peer, protocol, handler).await {
+                        tracing::warn!(%peer, "Failed to serve request: {error}");
+                    }
+                });
+            }
+        });
+
+        Ok(ServiceHandle { protocol, task })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use libp2p::futures::io::Cursor;
+
+    use super::*;
+
+    /// A stream reading from a fixed input and recording what is written to it
+    struct Exchange {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for Exchange {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Exchange {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.output).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.output).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.output).poll_close(cx)
+        }
+    }
+
+    fn protocol() -> StreamProtocol {
+        StreamProtocol::new("/test/echo")
+    }
+
+    /// Frames `payload` the way it is sent on a stream
+    async fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, payload).await.unwrap();
+        frame
+    }
+
+    /// Answers the request framed in `input`, returning what was sent back
+    async fn answer<Req, Resp, Fut, E>(
+        peer: PeerId,
+        input: Vec<u8>,
+        handler: impl Fn(PeerId, Req) -> Fut,
+    ) -> Vec<u8>
+    where
+        Req: DeserializeOwned,
+        Resp: Serialize,
+        Fut: Future<Output = Result<Resp, E>>,
+        E: Display,
+    {
+        let mut stream = Exchange {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        respond(&mut stream, peer, protocol(), handler).await.unwrap();
+        stream.output
+    }
+
+    /// Sends `request` to a peer answering with the framed `response`, returning the result and
+    /// what was sent
+    async fn ask<Resp: DeserializeOwned>(
+        peer: PeerId,
+        request: &impl Serialize,
+        response: Vec<u8>,
+    ) -> (CResult<Resp>, Vec<u8>) {
+        let mut stream = Exchange {
+            input: Cursor::new(response),
+            output: Vec::new(),
+        };
+        let payload = RPC_FORMAT.encode(request).unwrap();
+        let open = async { Ok(&mut stream) };
+        let result = exchange(open, peer, protocol(), &payload, DEFAULT_REQUEST_TIMEOUT).await;
+        (result, stream.output)
+    }
+
+    async fn double(_: PeerId, value: u32) -> Result<u32, String> {
+        value.checked_mul(2).ok_or_else(|| String::from("overflow"))
+    }
+
+    #[tokio::test]
+    async fn request_round_trips() {
+        let peer = PeerId::random();
+        let request = frame(&RPC_FORMAT.encode(&21u32).unwrap()).await;
+        let response = answer(peer, request.clone(), double).await;
+
+        let (result, sent) = ask::<u32>(peer, &21u32, response).await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(sent, request);
+    }
+
+    #[tokio::test]
+    async fn handler_errors_are_returned_as_remote_errors() {
+        let peer = PeerId::random();
+        let request = frame(&RPC_FORMAT.encode(&u32::MAX).unwrap()).await;
+        let response = answer(peer, request, double).await;
+
+        let (result, _) = ask::<u32>(peer, &u32::MAX, response).await;
+        assert!(matches!(
+            result,
+            Err(Error::Remote {
+                peer: remote,
+                error: RemoteError { kind: RemoteErrorKind::Handler, message },
+            }) if remote == peer && message == "overflow"
+        ));
+    }
+
+    #[tokio::test]
+    async fn undecodable_requests_are_rejected() {
+        let peer = PeerId::random();
+        let request = frame(&RPC_FORMAT.encode(&"not a number").unwrap()).await;
+        let response = answer(peer, request, double).await;
+
+        let (result, _) = ask::<u32>(peer, &"not a number", response).await;
+        assert!(matches!(
+            result,
+            Err(Error::Remote {
+                error: RemoteError { kind: RemoteErrorKind::InvalidRequest, .. },
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn requests_time_out() {
+        let peer = PeerId::random();
+        let payload = RPC_FORMAT.encode(&21u32).unwrap();
+        let open = std::future::pending::<CResult<Exchange>>();
+        let timeout = Duration::from_millis(10);
+
+        assert!(matches!(
+            exchange::<_, u32>(open, peer, protocol(), &payload, timeout).await,
+            Err(Error::RequestTimeout { peer: remote, protocol }) if remote == peer
+                && protocol == "/test/echo"
+        ));
+    }
+}
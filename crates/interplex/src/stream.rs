@@ -0,0 +1,244 @@
+use std::{
+    fmt::Debug,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll},
+};
+
+use async_channel::Sender;
+use libp2p::{
+    futures::{self, task::AtomicWaker, AsyncRead, AsyncWrite},
+    PeerId, Stream, StreamProtocol,
+};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::{
+    error::{CResult, Error},
+    ipc::StreamRole,
+};
+
+pub(crate) fn parse_protocol(protocol: impl Into<String>) -> CResult<StreamProtocol> {
+    let protocol: String = protocol.into();
+    StreamProtocol::try_from_owned(protocol.clone()).map_err(|_| Error::InvalidProtocol(protocol))
+}
+
+/// A stream shared between its handle and the network, which closes it when the node stops
+pub(crate) struct SharedStream {
+    inner: std::sync::Mutex<Stream>,
+    closed: AtomicBool,
+    read_waker: AtomicWaker,
+    write_waker: AtomicWaker,
+}
+
+impl SharedStream {
+    pub(crate) fn new(inner: Stream) -> Self {
+        Self {
+            inner: std::sync::Mutex::new(inner),
+            closed: AtomicBool::new(false),
+            read_waker: AtomicWaker::new(),
+            write_waker: AtomicWaker::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Stream> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Registers the waker to be woken on shutdown, then checks whether the stream was shut down
+    fn is_closed(&self, waker: &AtomicWaker, cx: &Context<'_>) -> bool {
+        waker.register(cx.waker());
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Closes the stream on behalf of its handle, which then reads EOF and fails to write
+    pub(crate) async fn shutdown(&self) -> io::Result<()> {
+        self.closed.store(true, Ordering::Release);
+        self.read_waker.wake();
+        self.write_waker.wake();
+        futures::future::poll_fn(|cx| Pin::new(&mut *self.lock()).poll_close(cx)).await
+    }
+}
+
+/// A byte stream to a remote peer.
+///
+/// Implements [`AsyncRead`] and [`AsyncWrite`] from `futures`, as well as the `tokio` equivalents
+/// when the `tokio-io` feature is enabled. The stream is closed when dropped, at which point a
+/// [`crate::NodeEvent::StreamClosed`] event is emitted. Stopping the node also closes the stream,
+/// after which reads return EOF and writes fail.
+pub struct InterplexStream {
+    id: Uuid,
+    peer: PeerId,
+    role: StreamRole,
+    protocol: StreamProtocol,
+    shared: Arc<SharedStream>,
+    dropped: Sender<Uuid>,
+}
+
+impl InterplexStream {
+    pub(crate) fn new(
+        peer: PeerId,
+        role: StreamRole,
+        protocol: StreamProtocol,
+        shared: Arc<SharedStream>,
+        dropped: Sender<Uuid>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            peer,
+            role,
+            protocol,
+            shared,
+            dropped,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn peer(&self) -> PeerId {
+        self.peer
+    }
+
+    pub fn role(&self) -> StreamRole {
+        self.role
+    }
+
+    pub fn protocol(&self) -> StreamProtocol {
+        self.protocol.clone()
+    }
+}
+
+impl Debug for InterplexStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterplexStream")
+            .field("id", &self.id)
+            .field("peer", &self.peer)
+            .field("role", &self.role)
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+impl Drop for InterplexStream {
+    fn drop(&mut self) {
+        let _ = self.dropped.try_send(self.id);
+    }
+}
+
+impl AsyncRead for InterplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let shared = &self.shared;
+        if shared.is_closed(&shared.read_waker, cx) {
+            return Poll::Ready(Ok(0));
+        }
+        Pin::new(&mut *shared.lock()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for InterplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let shared = &self.shared;
+        if shared.is_closed(&shared.write_waker, cx) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stream was closed by the node",
+            )));
+        }
+        Pin::new(&mut *shared.lock()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let shared = &self.shared;
+        if shared.is_closed(&shared.write_waker, cx) {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut *shared.lock()).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let shared = &self.shared;
+        if shared.is_closed(&shared.write_waker, cx) {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut *shared.lock()).poll_close(cx)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncRead for InterplexStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let read = std::task::ready!(AsyncRead::poll_read(self, cx, buf.initialize_unfilled()))?;
+        buf.advance(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio::io::AsyncWrite for InterplexStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(self, cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(self, cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(self, cx)
+    }
+}
+
+/// Streams opened to this node on a single protocol, returned by
+/// [`crate::InterplexNode::accept_protocol`]. The protocol stops being accepted when this is dropped.
+pub struct InboundStreams {
+    protocol: StreamProtocol,
+    receiver: mpsc::Receiver<InterplexStream>,
+}
+
+impl InboundStreams {
+    pub(crate) fn new(protocol: StreamProtocol, receiver: mpsc::Receiver<InterplexStream>) -> Self {
+        Self { protocol, receiver }
+    }
+
+    pub fn protocol(&self) -> StreamProtocol {
+        self.protocol.clone()
+    }
+}
+
+impl Debug for InboundStreams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InboundStreams")
+            .field("protocol", &self.protocol)
+            .finish()
+    }
+}
+
+impl futures::Stream for InboundStreams {
+    type Item = InterplexStream;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
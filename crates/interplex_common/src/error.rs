@@ -3,6 +3,7 @@ use std::fmt::Debug;
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug, Clone, Serialize, Deserialize)]
 pub enum InterplexError {
@@ -28,7 +29,19 @@ pub enum InterplexError {
     RequestDispatch {peer: PeerId, namespace: String, command: String},
 
     #[error("Improperly specified address: {addr}: {reason}")]
-    Address {addr: String, reason: String}
+    Address {addr: String, reason: String},
+
+    #[error("Request claimed to be sent by {claimed}, but was sent by {actual}")]
+    SourceMismatch {claimed: PeerId, actual: PeerId},
+
+    #[error("Invalid peer record: {0}")]
+    InvalidRecord(String),
+
+    #[error("A watch with ID {0} already exists")]
+    WatchExists(Uuid),
+
+    #[error("Invalid discovery cursor: {0}")]
+    InvalidCursor(String)
 }
 
 impl InterplexError {
@@ -7,11 +7,32 @@ use serde_cbor::Value;
 
 use crate::error::{IResult, InterplexError};
 
+/// Which nodes may see a node's registration through a rendezvous server. Nodes only ever see
+/// registrations of their own namespace, and may always find their own.
+///
+/// | Mode        | Discover                          | Find (by key)                     |
+/// |-------------|-----------------------------------|-----------------------------------|
+/// | `Namespace` | any node                          | any node                          |
+/// | `Group`     | nodes of the same group           | nodes of the same group           |
+/// | `Direct`    | no node                           | any node                          |
+/// | `Private`   | listed peers and groups           | listed peers and groups           |
+///
+/// Registrations that may not be found are answered like missing ones.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Discoverability {
     Namespace,
     Group,
     Direct,
+
+    /// Only visible to the listed peers, and to the nodes of the listed groups. Peer IDs are
+    /// authenticated by the connection, whereas groups are claimed by the requesting node.
+    Private {
+        #[serde(default)]
+        peers: Vec<PeerId>,
+
+        #[serde(default)]
+        groups: Vec<String>,
+    },
 }
 
 impl Default for Discoverability {
@@ -1,18 +1,20 @@
 use std::{
-    collections::HashMap,
-    task::{Context, Poll}, time::Duration,
+    collections::{HashMap, VecDeque},
+    task::{Context, Poll},
+    time::Duration,
 };
 
 use chrono::{DateTime, TimeDelta, Utc};
 use libp2p::{
     core::{transport::PortUse, Endpoint},
     futures::{self, future::BoxFuture, stream::FuturesUnordered, FutureExt, StreamExt as _},
-    request_response::{Config, OutboundRequestId, ProtocolSupport},
+    identity::Keypair,
+    request_response::{OutboundRequestId, ProtocolSupport},
     swarm::{
-        ConnectionDenied, ConnectionId, ExternalAddresses, FromSwarm, NetworkBehaviour, THandler,
-        THandlerInEvent, THandlerOutEvent, ToSwarm,
+        behaviour::ConnectionClosed, ConnectionDenied, ConnectionId, ExternalAddresses, FromSwarm,
+        NetworkBehaviour, THandler, THandlerInEvent, THandlerOutEvent, ToSwarm,
     },
-    Multiaddr, PeerId, StreamProtocol,
+    Multiaddr, PeerId,
 };
 use uuid::Uuid;
 
@@ -22,21 +24,43 @@ use crate::{
 };
 
 use super::{
-    message::{RendezvousCommand, RendezvousRequest, RendezvousResponse},
+    message::{
+        DiscoverPage, Protocols, ProtocolsEvent, RegistrationLease, RendezvousCommand,
+        RendezvousRequest, RendezvousResponse, WatchNotification, WatchRequest,
+    },
+    query::Query,
+    record::{PeerRecord, SignedPeerRecord},
     registrations::Registration,
 };
 
 pub struct Behaviour {
-    inner: libp2p::request_response::cbor::Behaviour<RendezvousRequest, RendezvousResponse>,
+    inner: Protocols,
     identity: NodeIdentifier,
+    keypair: Keypair,
+    ttl: Option<TimeDelta>,
     processing_requests: HashMap<OutboundRequestId, (PeerId, RendezvousCommand)>,
     peers: HashMap<PeerId, (PeerId, Registration, Uuid)>, // {peer_id: (rdv_id, peer)}
     expiring_peers: FuturesUnordered<BoxFuture<'static, (PeerId, Uuid)>>,
     expiring_registrations: FuturesUnordered<BoxFuture<'static, (PeerId, Uuid)>>,
     addresses: ExternalAddresses,
     rendezvous_points: HashMap<PeerId, (DateTime<Utc>, Uuid)>,
+    pending_events: VecDeque<Event>,
+    discovery_walks: HashMap<OutboundRequestId, DiscoveryWalk>,
+    watches: HashMap<Uuid, PeerId>, // {watch_id: rdv_id}
+    pending_watches: HashMap<Uuid, (PeerId, Vec<WatchNotification>)>, // {watch_id: (rdv_id, early notifications)}
+}
+
+/// A discovery walking every page, keyed by the request of its current page
+struct DiscoveryWalk {
+    /// The request of the first page, which the aggregated event is reported under
+    request: OutboundRequestId,
+    peers: Vec<Registration>,
+
+    /// Watch whose first peers are being received, reported by [`Event::Watching`] once complete
+    watch: Option<Uuid>,
 }
 
+/// How long before its expiration a registration is refreshed, or half its TTL if that is shorter
 const REGISTRATION_BUFFER: TimeDelta = TimeDelta::minutes(1);
 
 #[derive(Clone, Debug)]
@@ -45,6 +69,9 @@ pub enum Event {
         request: OutboundRequestId,
         rendezvous_node: PeerId,
         lifetime: DateTime<Utc>,
+
+        /// TTL granted by the rendezvous node, which may differ from the requested one
+        ttl: TimeDelta,
     },
     RegisterFailed {
         request: OutboundRequestId,
@@ -60,10 +87,15 @@ pub enum Event {
         rendezvous_node: PeerId,
         error: InterplexError,
     },
+    /// A page of discovered peers, or every page of a discovery started with
+    /// [`Behaviour::discover_all`]
     Discovered {
         request: OutboundRequestId,
         rendezvous_node: PeerId,
         peers: Vec<Registration>,
+
+        /// Cursor of the next page, always None for [`Behaviour::discover_all`]
+        next_cursor: Option<String>,
     },
     DiscoverFailed {
         request: OutboundRequestId,
@@ -103,13 +135,65 @@ pub enum Event {
     RegistrationExpired {
         rendezvous_node: PeerId,
     },
+    Watching {
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+        watch: Uuid,
+
+        /// Peers the watch covered when it was created, gathered from every page of them. Changes
+        /// made since are reported after this event.
+        peers: Vec<Registration>,
+    },
+    WatchFailed {
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+        error: InterplexError,
+    },
+    Unwatched {
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+    },
+    UnwatchFailed {
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+        error: InterplexError,
+    },
+    /// A peer registered in the scope of a watch, or changed to match it
+    Joined {
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        peer: Registration,
+    },
+    /// A peer in the scope of a watch refreshed or changed its registration
+    Updated {
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        peer: Registration,
+    },
+    /// A peer in the scope of a watch deregistered, or changed to no longer match it
+    Left {
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        peer: Registration,
+    },
+    /// The registration of a peer in the scope of a watch expired on the rendezvous node. Unlike
+    /// [`Event::PeerExpired`], this is reported by the rendezvous node itself.
+    Expired {
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        peer: Registration,
+    },
+    /// A rendezvous node returned a registration whose signed record failed verification. The
+    /// registration is not added to the known peers.
+    InvalidRecord {
+        rendezvous_node: PeerId,
+        registration: Registration,
+        error: InterplexError,
+    },
 }
 
 impl NetworkBehaviour for Behaviour {
-    type ConnectionHandler = <libp2p::request_response::cbor::Behaviour<
-        RendezvousRequest,
-        RendezvousResponse,
-    > as NetworkBehaviour>::ConnectionHandler;
+    type ConnectionHandler = <Protocols as NetworkBehaviour>::ConnectionHandler;
     type ToSwarm = Event;
 
     fn handle_established_inbound_connection(
@@ -157,6 +241,19 @@ impl NetworkBehaviour for Behaviour {
     fn on_swarm_event(&mut self, event: FromSwarm) {
         let changed = self.addresses.on_swarm_event(&event);
 
+        // Rendezvous nodes drop the watches of disconnected peers
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            remaining_established: 0,
+            ..
+        }) = event
+        {
+            self.watches
+                .retain(|_, rendezvous_node| rendezvous_node != &peer_id);
+            self.pending_watches
+                .retain(|_, (rendezvous_node, _)| rendezvous_node != &peer_id);
+        }
+
         self.inner.on_swarm_event(event);
 
         if changed && self.addresses.iter().count() > 0 {
@@ -199,42 +296,67 @@ impl NetworkBehaviour for Behaviour {
     ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
         use libp2p::request_response as req_res;
 
+        if let Some(event) = self.pending_events.pop_front() {
+            return Poll::Ready(ToSwarm::GenerateEvent(event));
+        }
+
         loop {
             match self.inner.poll(cx) {
-                Poll::Ready(ToSwarm::GenerateEvent(req_res::Event::Message {
-                    message:
-                        req_res::Message::Response {
-                            request_id,
-                            response,
-                        },
-                    ..
-                })) => {
+                Poll::Ready(ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(
+                    req_res::Event::Message {
+                        message:
+                            req_res::Message::Response {
+                                request_id,
+                                response,
+                            },
+                        ..
+                    },
+                ))) => {
                     if let Some(event) = self.handle_response(&request_id, response) {
                         return Poll::Ready(ToSwarm::GenerateEvent(event));
                     }
 
                     continue; // not a request we care about
                 }
-                Poll::Ready(ToSwarm::GenerateEvent(req_res::Event::OutboundFailure {
-                    request_id,
-                    ..
-                })) => {
+                Poll::Ready(ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(
+                    req_res::Event::OutboundFailure { request_id, .. },
+                ))) => {
                     if let Some(event) = self.event_for_outbound_failure(&request_id) {
                         return Poll::Ready(ToSwarm::GenerateEvent(event));
                     }
 
                     continue; // not a request we care about
                 }
-                Poll::Ready(ToSwarm::GenerateEvent(
+                Poll::Ready(ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(
                     req_res::Event::InboundFailure { .. }
                     | req_res::Event::ResponseSent { .. }
                     | req_res::Event::Message {
                         message: req_res::Message::Request { .. },
                         ..
                     },
-                )) => {
+                ))) => {
                     unreachable!("rendezvous clients never receive requests")
                 }
+                Poll::Ready(ToSwarm::GenerateEvent(ProtocolsEvent::Watch(
+                    req_res::Event::Message {
+                        peer,
+                        message:
+                            req_res::Message::Request {
+                                request, channel, ..
+                            },
+                        ..
+                    },
+                ))) => {
+                    let _ = self.inner.watch.send_response(channel, ());
+                    if let Some(event) = self.handle_notification(peer, request) {
+                        return Poll::Ready(ToSwarm::GenerateEvent(event));
+                    }
+
+                    continue; // not a watch we know of
+                }
+                Poll::Ready(ToSwarm::GenerateEvent(ProtocolsEvent::Watch(_))) => {
+                    continue; // acknowledgements and their failures
+                }
                 Poll::Ready(other) => {
                     let new_to_swarm =
                         other.map_out(|_| unreachable!("we manually map `GenerateEvent` variants"));
@@ -244,13 +366,14 @@ impl NetworkBehaviour for Behaviour {
                 Poll::Pending => {}
             }
 
-            if let Poll::Ready(Some((expired_peer, key))) =
-                self.expiring_peers.poll_next_unpin(cx)
+            if let Poll::Ready(Some((expired_peer, key))) = self.expiring_peers.poll_next_unpin(cx)
             {
-                if let Some((peer, registration, current_key)) = self.peers.clone().get(&expired_peer) {
+                if let Some((peer, registration, current_key)) =
+                    self.peers.clone().get(&expired_peer)
+                {
                     if current_key == &key {
                         self.peers.remove(&expired_peer);
-                            return Poll::Ready(ToSwarm::GenerateEvent(Event::PeerExpired {
+                        return Poll::Ready(ToSwarm::GenerateEvent(Event::PeerExpired {
                             rendezvous_node: peer.clone(),
                             registration: registration.clone(),
                         }));
@@ -261,10 +384,14 @@ impl NetworkBehaviour for Behaviour {
             if let Poll::Ready(Some((expired_registration, key))) =
                 self.expiring_registrations.poll_next_unpin(cx)
             {
-                if let Some((_, current_key)) = self.rendezvous_points.clone().get(&expired_registration) {
+                if let Some((_, current_key)) =
+                    self.rendezvous_points.clone().get(&expired_registration)
+                {
                     if current_key == &key {
                         self.rendezvous_points.remove(&expired_registration);
-                        return Poll::Ready(ToSwarm::GenerateEvent(Event::RegistrationExpired { rendezvous_node: expired_registration.clone()}));
+                        return Poll::Ready(ToSwarm::GenerateEvent(Event::RegistrationExpired {
+                            rendezvous_node: expired_registration.clone(),
+                        }));
                     }
                 }
             }
@@ -275,28 +402,31 @@ impl NetworkBehaviour for Behaviour {
 }
 
 impl Behaviour {
-    pub fn new(identifier: NodeIdentifier) -> Self {
+    /// Creates the client of a node. Registrations are signed with `keypair`, which must be the
+    /// node's own.
+    pub fn new(identifier: NodeIdentifier, keypair: Keypair) -> Self {
         Self {
-            inner: libp2p::request_response::cbor::Behaviour::<RendezvousRequest, RendezvousResponse>::new([(
-                StreamProtocol::new("/interplex/rendezvous"),
-                ProtocolSupport::Full,
-            )], Config::default()),
+            inner: Protocols::new(ProtocolSupport::Outbound, ProtocolSupport::Inbound),
             identity: identifier,
+            keypair,
+            ttl: None,
             processing_requests: Default::default(),
             peers: Default::default(),
-            expiring_peers: FuturesUnordered::from_iter(vec![
-                futures::future::pending().boxed()
-            ]),
+            expiring_peers: FuturesUnordered::from_iter(vec![futures::future::pending().boxed()]),
             expiring_registrations: FuturesUnordered::from_iter(vec![
                 futures::future::pending().boxed()
             ]),
             addresses: Default::default(),
-            rendezvous_points: Default::default()
+            rendezvous_points: Default::default(),
+            pending_events: Default::default(),
+            discovery_walks: Default::default(),
+            watches: Default::default(),
+            pending_watches: Default::default(),
         }
     }
 
     fn send_request(&mut self, target: &PeerId, command: RendezvousCommand) -> OutboundRequestId {
-        let req_id = self.inner.send_request(
+        let req_id = self.inner.rendezvous.send_request(
             target,
             RendezvousRequest {
                 source: self.identity.clone(),
@@ -310,32 +440,79 @@ impl Behaviour {
 
     pub fn register(&mut self, target: &PeerId) -> IResult<OutboundRequestId> {
         if self.addresses.as_slice().len() > 0 {
+            let record = SignedPeerRecord::new(
+                &self.keypair,
+                &PeerRecord {
+                    identity: self.identity.clone(),
+                    addresses: self.addresses.as_slice().to_vec(),
+                    timestamp: Utc::now(),
+                },
+            )?;
             Ok(self.send_request(
                 target,
-                RendezvousCommand::Register(self.addresses.as_slice().to_vec()),
+                RendezvousCommand::Register {
+                    record,
+                    ttl: self.ttl,
+                },
             ))
         } else {
             Err(InterplexError::NodeInaccessible)
         }
     }
 
+    /// Sets the TTL requested by later registrations, or None to leave it to the rendezvous nodes.
+    /// Rendezvous nodes clamp it to the range they allow.
+    pub fn set_ttl(&mut self, ttl: Option<TimeDelta>) {
+        self.ttl = ttl;
+    }
+
     pub fn deregister(&mut self, target: &PeerId) -> OutboundRequestId {
         let rid = self.send_request(target, RendezvousCommand::Deregister);
         self.rendezvous_points.remove(target);
         rid
     }
 
+    /// Requests a single page of peers matching `query` (if any), of up to `limit` peers (or the
+    /// server's maximum page size), starting at `cursor`
     pub fn discover(
         &mut self,
         target: &PeerId,
         group: Option<String>,
+        query: Option<Query>,
+        limit: Option<u32>,
+        cursor: Option<String>,
     ) -> OutboundRequestId {
         self.send_request(
             target,
-            RendezvousCommand::Discover(group.and_then(|g| Some(g))),
+            RendezvousCommand::Discover {
+                group,
+                query,
+                limit,
+                cursor,
+            },
         )
     }
 
+    /// Requests every page of peers matching `query` (if any), reported as a single
+    /// [`Event::Discovered`] once the last page is received
+    pub fn discover_all(
+        &mut self,
+        target: &PeerId,
+        group: Option<String>,
+        query: Option<Query>,
+    ) -> OutboundRequestId {
+        let request = self.discover(target, group, query, None, None);
+        self.discovery_walks.insert(
+            request,
+            DiscoveryWalk {
+                request,
+                peers: Vec::new(),
+                watch: None,
+            },
+        );
+        request
+    }
+
     pub fn find(
         &mut self,
         target: &PeerId,
@@ -356,23 +533,187 @@ impl Behaviour {
         self.send_request(target, RendezvousCommand::Groups)
     }
 
+    /// Subscribes to changes of the peers that [`Behaviour::discover`] would return with the same
+    /// group and filter. The peers covered at first are reported by [`Event::Watching`] once every
+    /// page of them is received, so no discovery is needed beforehand. If a page can't be
+    /// received, the watch is cancelled and [`Event::WatchFailed`] is reported instead. Changes are then reported as [`Event::Joined`],
+    /// [`Event::Updated`], [`Event::Left`] and [`Event::Expired`] until the watch is cancelled, or
+    /// the connection to the rendezvous node is closed.
+    pub fn watch(
+        &mut self,
+        target: &PeerId,
+        group: Option<String>,
+        filter: Option<Query>,
+    ) -> OutboundRequestId {
+        // Changes may be pushed before the response, so they are kept until it arrives
+        let watch = Uuid::new_v4();
+        self.pending_watches.insert(watch, (*target, Vec::new()));
+        self.send_request(
+            target,
+            RendezvousCommand::Watch {
+                watch,
+                group,
+                filter,
+            },
+        )
+    }
+
+    pub fn unwatch(&mut self, target: &PeerId, watch: Uuid) -> OutboundRequestId {
+        let rid = self.send_request(target, RendezvousCommand::Unwatch(watch));
+        self.watches.remove(&watch);
+        self.pending_watches.remove(&watch);
+        rid
+    }
+
+    /// Active watches, with the rendezvous node of each. Watches awaiting their response aren't
+    /// included.
+    pub fn watches(&self) -> HashMap<Uuid, PeerId> {
+        self.watches.clone()
+    }
+
     pub fn peers(&self) -> HashMap<PeerId, Registration> {
-        self.peers.iter().map(|(k, (_, v, _))| (k.clone(), v.clone())).collect()
+        self.peers
+            .iter()
+            .map(|(k, (_, v, _))| (k.clone(), v.clone()))
+            .collect()
     }
 
     pub fn rendezvous_points(&self) -> Vec<PeerId> {
         self.rendezvous_points.keys().cloned().collect()
     }
 
+    /// Adds a verified registration to the known peers, until it expires
+    fn insert_peer(&mut self, rendezvous_node: &PeerId, registration: Registration) {
+        let key = Uuid::new_v4();
+        let async_target = registration.identity.peer_id;
+        let async_expire = registration.expiration();
+        self.peers
+            .insert(async_target, (*rendezvous_node, registration, key));
+        self.expiring_peers.push(
+            async move {
+                futures_timer::Delay::new(
+                    (async_expire - Utc::now())
+                        .to_std()
+                        .expect("Refresh time out of int range")
+                        .max(Duration::from_secs(0)),
+                )
+                .await;
+                (async_target, key)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Removes a peer from the known peers, unless it was since found through another rendezvous
+    /// node
+    fn remove_peer(&mut self, rendezvous_node: &PeerId, registration: &Registration) {
+        let peer_id = registration.identity.peer_id;
+        if self
+            .peers
+            .get(&peer_id)
+            .is_some_and(|(rdv, _, _)| rdv == rendezvous_node)
+        {
+            self.peers.remove(&peer_id);
+        }
+    }
+
+    /// Verifies a page of registrations and adds them to the known peers. Registrations with an
+    /// invalid record are reported and left out.
+    fn insert_page(
+        &mut self,
+        rendezvous_node: &PeerId,
+        registrations: Vec<Registration>,
+    ) -> Vec<Registration> {
+        let mut verified = Vec::with_capacity(registrations.len());
+        for registration in registrations {
+            match registration.verified() {
+                Ok(registration) => {
+                    self.insert_peer(rendezvous_node, registration.clone());
+                    verified.push(registration);
+                }
+                Err(error) => self.pending_events.push_back(Event::InvalidRecord {
+                    rendezvous_node: *rendezvous_node,
+                    registration,
+                    error,
+                }),
+            }
+        }
+
+        verified
+    }
+
+    /// Activates a pending watch once all of its first peers are received, and replays the
+    /// changes pushed meanwhile after it
+    fn watching(
+        &mut self,
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        peers: Vec<Registration>,
+    ) -> Option<Event> {
+        // Watches cancelled in the meantime are left out
+        let (_, received) = self.pending_watches.remove(&watch)?;
+        self.watches.insert(watch, rendezvous_node);
+
+        for notification in received {
+            if let Some(event) = self.handle_notification(
+                rendezvous_node,
+                WatchRequest {
+                    watch,
+                    notification,
+                },
+            ) {
+                self.pending_events.push_back(event);
+            }
+        }
+
+        Some(Event::Watching {
+            request,
+            rendezvous_node,
+            watch,
+            peers,
+        })
+    }
+
+    /// Gives up on a watch whose first peers couldn't all be discovered, cancelling it on the
+    /// rendezvous node if it is still pending
+    fn abandon_watch(
+        &mut self,
+        request: OutboundRequestId,
+        rendezvous_node: PeerId,
+        watch: Uuid,
+        error: InterplexError,
+    ) -> Event {
+        if self.pending_watches.remove(&watch).is_some() {
+            self.send_request(&rendezvous_node, RendezvousCommand::Unwatch(watch));
+        }
+
+        Event::WatchFailed {
+            request,
+            rendezvous_node,
+            error,
+        }
+    }
+
     fn event_for_outbound_failure(&mut self, req_id: &OutboundRequestId) -> Option<Event> {
         if let Some((target, command)) = self.processing_requests.remove(req_id) {
+            // Failures of a walk's pages are reported under its first request
+            let walk = self.discovery_walks.remove(req_id);
+            let req_id = &walk.as_ref().map_or(*req_id, |walk| walk.request);
             let err = InterplexError::RequestDispatch {
                 peer: target.clone(),
                 namespace: String::from("rendezvous"),
                 command: format!("{:?}", command.clone()),
             };
+            if let RendezvousCommand::Watch { watch, .. } = &command {
+                self.pending_watches.remove(watch);
+            }
+            if let Some(watch) = walk.and_then(|walk| walk.watch) {
+                return Some(self.abandon_watch(*req_id, target, watch, err));
+            }
+
             Some(match command {
-                RendezvousCommand::Register(_) => Event::RegisterFailed {
+                RendezvousCommand::Register { .. } => Event::RegisterFailed {
                     request: *req_id,
                     rendezvous_node: target,
                     error: err,
@@ -382,7 +723,7 @@ impl Behaviour {
                     rendezvous_node: target,
                     error: err,
                 },
-                RendezvousCommand::Discover(_) => Event::DiscoverFailed {
+                RendezvousCommand::Discover { .. } => Event::DiscoverFailed {
                     request: *req_id,
                     rendezvous_node: target,
                     error: err,
@@ -397,66 +738,297 @@ impl Behaviour {
                     rendezvous_node: target,
                     error: err,
                 },
+                RendezvousCommand::Watch { .. } => Event::WatchFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: err,
+                },
+                RendezvousCommand::Unwatch(_) => Event::UnwatchFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: err,
+                },
             })
         } else {
             None
         }
     }
 
-    fn handle_response(&mut self, req_id: &OutboundRequestId, response: RendezvousResponse) -> Option<Event> {
+    fn handle_response(
+        &mut self,
+        req_id: &OutboundRequestId,
+        response: RendezvousResponse,
+    ) -> Option<Event> {
         if let Some((target, command)) = self.processing_requests.remove(req_id) {
             match response {
-                RendezvousResponse::Register(Ok(next_registration)) => {
+                RendezvousResponse::Register(Ok(RegistrationLease { ttl, expiration })) => {
                     let key = Uuid::new_v4();
-                    self.rendezvous_points.insert(target.clone(), (next_registration.clone(), key.clone()));
-                    let async_target = target.clone();
-                    let async_expire = next_registration.clone();
-                    self.expiring_registrations.push(async move {
-                        futures_timer::Delay::new((async_expire - Utc::now() - REGISTRATION_BUFFER).to_std().expect("Refresh time out of int range").max(Duration::from_secs(0))).await;
-                        (async_target, key)
-                    }.boxed());
-                    Some(Event::Registered { request: *req_id, rendezvous_node: target, lifetime: next_registration })
-                },
-                RendezvousResponse::Register(Err(e)) => Some(Event::RegisterFailed { request: *req_id, rendezvous_node: target, error: e }),
+                    self.rendezvous_points.insert(target, (expiration, key));
+                    let async_target = target;
+                    let refresh = expiration - REGISTRATION_BUFFER.min(ttl / 2);
+                    self.expiring_registrations.push(
+                        async move {
+                            futures_timer::Delay::new(
+                                (refresh - Utc::now()).to_std().unwrap_or_default(),
+                            )
+                            .await;
+                            (async_target, key)
+                        }
+                        .boxed(),
+                    );
+                    Some(Event::Registered {
+                        request: *req_id,
+                        rendezvous_node: target,
+                        lifetime: expiration,
+                        ttl,
+                    })
+                }
+                RendezvousResponse::Register(Err(e)) => Some(Event::RegisterFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: e,
+                }),
                 RendezvousResponse::Deregister(Ok(_)) => {
                     self.rendezvous_points.remove(&target);
-                    Some(Event::Deregistered { request: *req_id, rendezvous_node: target })
-                },
-                RendezvousResponse::Deregister(Err(e)) => Some(Event::DeregisterFailed { request: *req_id, rendezvous_node: target, error: e }),
-                RendezvousResponse::Discover(Ok(registrations)) => {
-                    for registration in registrations.clone() {
-                        let key = Uuid::new_v4();
-                        self.peers.insert(registration.identity.peer_id.clone(), (target.clone(), registration.clone(), key.clone()));
-                        let async_target = registration.identity.peer_id.clone();
-                        let async_expire = registration.expiration();
-                        self.expiring_peers.push(async move {
-                            futures_timer::Delay::new((async_expire - Utc::now()).to_std().expect("Refresh time out of int range").max(Duration::from_secs(0))).await;
-                            (async_target, key)
-                        }.boxed());
-                    }
+                    Some(Event::Deregistered {
+                        request: *req_id,
+                        rendezvous_node: target,
+                    })
+                }
+                RendezvousResponse::Deregister(Err(e)) => Some(Event::DeregisterFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: e,
+                }),
+                RendezvousResponse::Discover(Ok(DiscoverPage {
+                    registrations,
+                    next_cursor,
+                })) => {
+                    let registrations_empty = registrations.is_empty();
+                    let verified = self.insert_page(&target, registrations);
+
+                    if let Some(mut walk) = self.discovery_walks.remove(req_id) {
+                        walk.peers.extend(verified);
 
-                    Some(Event::Discovered { request: *req_id, rendezvous_node: target, peers: registrations })
+                        // An empty page can't advance the walk, whatever its cursor
+                        match next_cursor.filter(|_| !registrations_empty) {
+                            Some(cursor) => {
+                                let (group, query) = match command {
+                                    RendezvousCommand::Discover { group, query, .. } => {
+                                        (group, query)
+                                    }
+                                    _ => (None, None),
+                                };
+                                let next = self.discover(&target, group, query, None, Some(cursor));
+                                self.discovery_walks.insert(next, walk);
+                                None
+                            }
+                            None => match walk.watch {
+                                Some(watch) => {
+                                    self.watching(walk.request, target, watch, walk.peers)
+                                }
+                                None => Some(Event::Discovered {
+                                    request: walk.request,
+                                    rendezvous_node: target,
+                                    peers: walk.peers,
+                                    next_cursor: None,
+                                }),
+                            },
+                        }
+                    } else {
+                        Some(Event::Discovered {
+                            request: *req_id,
+                            rendezvous_node: target,
+                            peers: verified,
+                            next_cursor,
+                        })
+                    }
+                }
+                RendezvousResponse::Discover(Err(e)) => match self.discovery_walks.remove(req_id) {
+                    Some(DiscoveryWalk {
+                        request,
+                        watch: Some(watch),
+                        ..
+                    }) => Some(self.abandon_watch(request, target, watch, e)),
+                    walk => Some(Event::DiscoverFailed {
+                        request: walk.map_or(*req_id, |walk| walk.request),
+                        rendezvous_node: target,
+                        error: e,
+                    }),
                 },
-                RendezvousResponse::Discover(Err(e)) => Some(Event::DiscoverFailed { request: *req_id, rendezvous_node: target, error: e }),
-                RendezvousResponse::Find(Ok(Some(registration))) => {
-                    let key = Uuid::new_v4();
-                    self.peers.insert(registration.identity.peer_id.clone(), (target.clone(), registration.clone(), key.clone()));
-                    let async_target = registration.identity.peer_id.clone();
-                    let async_expire = registration.expiration();
-                    self.expiring_peers.push(async move {
-                        futures_timer::Delay::new((async_expire - Utc::now()).to_std().expect("Refresh time out of int range").max(Duration::from_secs(0))).await;
-                        (async_target, key)
-                    }.boxed());
-                    
-                    Some(Event::Found {request: *req_id, rendezvous_node: target, key: registration.clone().identity.key(), peer: registration})
+                RendezvousResponse::Find(Ok(Some(registration))) => match registration.verified() {
+                    Ok(registration) => {
+                        self.insert_peer(&target, registration.clone());
+                        Some(Event::Found {
+                            request: *req_id,
+                            rendezvous_node: target,
+                            key: registration.clone().identity.key(),
+                            peer: registration,
+                        })
+                    }
+                    Err(error) => Some(Event::InvalidRecord {
+                        rendezvous_node: target,
+                        registration,
+                        error,
+                    }),
                 },
-                RendezvousResponse::Find(Ok(None)) => Some(Event::NotFound { request: *req_id, rendezvous_node: target, key: if let RendezvousCommand::Find(key) = command {key} else {String::new()} }),
-                RendezvousResponse::Find(Err(e)) => Some(Event::FindFailed { request: *req_id, rendezvous_node: target, error: e }),
-                RendezvousResponse::Groups(Ok(groups)) => Some(Event::Groups { request: *req_id, rendezvous_node: target, groups }),
-                RendezvousResponse::Groups(Err(e)) => Some(Event::GroupsFailed { request: *req_id, rendezvous_node: target, error: e })
+                RendezvousResponse::Find(Ok(None)) => Some(Event::NotFound {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    key: if let RendezvousCommand::Find(key) = command {
+                        key
+                    } else {
+                        String::new()
+                    },
+                }),
+                RendezvousResponse::Find(Err(e)) => Some(Event::FindFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: e,
+                }),
+                RendezvousResponse::Groups(Ok(groups)) => Some(Event::Groups {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    groups,
+                }),
+                RendezvousResponse::Groups(Err(e)) => Some(Event::GroupsFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: e,
+                }),
+                RendezvousResponse::Watch(Ok(DiscoverPage {
+                    registrations,
+                    next_cursor,
+                })) => {
+                    let RendezvousCommand::Watch {
+                        watch,
+                        group,
+                        filter,
+                    } = command
+                    else {
+                        return None;
+                    };
+
+                    // Watches cancelled before the response was received are left out
+                    if !self.pending_watches.contains_key(&watch) {
+                        return None;
+                    }
+
+                    let registrations_empty = registrations.is_empty();
+                    let peers = self.insert_page(&target, registrations);
+
+                    // The watch stays pending, keeping the changes pushed meanwhile, until the
+                    // other pages of its peers are discovered
+                    match next_cursor.filter(|_| !registrations_empty) {
+                        Some(cursor) => {
+                            let next = self.discover(&target, group, filter, None, Some(cursor));
+                            self.discovery_walks.insert(
+                                next,
+                                DiscoveryWalk {
+                                    request: *req_id,
+                                    peers,
+                                    watch: Some(watch),
+                                },
+                            );
+                            None
+                        }
+                        None => self.watching(*req_id, target, watch, peers),
+                    }
+                }
+                RendezvousResponse::Watch(Err(e)) => {
+                    if let RendezvousCommand::Watch { watch, .. } = command {
+                        self.pending_watches.remove(&watch);
+                    }
+
+                    Some(Event::WatchFailed {
+                        request: *req_id,
+                        rendezvous_node: target,
+                        error: e,
+                    })
+                }
+                RendezvousResponse::Unwatch(Ok(_)) => Some(Event::Unwatched {
+                    request: *req_id,
+                    rendezvous_node: target,
+                }),
+                RendezvousResponse::Unwatch(Err(e)) => Some(Event::UnwatchFailed {
+                    request: *req_id,
+                    rendezvous_node: target,
+                    error: e,
+                }),
             }
         } else {
             None
         }
     }
+
+    /// Applies a notification pushed by a rendezvous node for one of its watches, or keeps it until
+    /// the watch is created if its response hasn't arrived yet
+    fn handle_notification(
+        &mut self,
+        rendezvous_node: PeerId,
+        request: WatchRequest,
+    ) -> Option<Event> {
+        // Notifications are only trusted from the rendezvous node the watch was created on
+        if let Some((node, received)) = self.pending_watches.get_mut(&request.watch) {
+            if *node == rendezvous_node {
+                received.push(request.notification);
+            }
+
+            return None;
+        }
+
+        if self.watches.get(&request.watch) != Some(&rendezvous_node) {
+            return None;
+        }
+
+        let watch = request.watch;
+        match request.notification {
+            WatchNotification::Joined(registration) => match registration.verified() {
+                Ok(peer) => {
+                    self.insert_peer(&rendezvous_node, peer.clone());
+                    Some(Event::Joined {
+                        rendezvous_node,
+                        watch,
+                        peer,
+                    })
+                }
+                Err(error) => Some(Event::InvalidRecord {
+                    rendezvous_node,
+                    registration,
+                    error,
+                }),
+            },
+            WatchNotification::Updated(registration) => match registration.verified() {
+                Ok(peer) => {
+                    self.insert_peer(&rendezvous_node, peer.clone());
+                    Some(Event::Updated {
+                        rendezvous_node,
+                        watch,
+                        peer,
+                    })
+                }
+                Err(error) => Some(Event::InvalidRecord {
+                    rendezvous_node,
+                    registration,
+                    error,
+                }),
+            },
+            WatchNotification::Left(peer) => {
+                self.remove_peer(&rendezvous_node, &peer);
+                Some(Event::Left {
+                    rendezvous_node,
+                    watch,
+                    peer,
+                })
+            }
+            WatchNotification::Expired(peer) => {
+                self.remove_peer(&rendezvous_node, &peer);
+                Some(Event::Expired {
+                    rendezvous_node,
+                    watch,
+                    peer,
+                })
+            }
+        }
+    }
 }
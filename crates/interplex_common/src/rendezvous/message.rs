@@ -1,10 +1,18 @@
-use chrono::{DateTime, Utc};
-use libp2p::Multiaddr;
+use chrono::{DateTime, TimeDelta, Utc};
+use libp2p::{
+    request_response::{cbor, Config, ProtocolSupport},
+    swarm::NetworkBehaviour,
+    StreamProtocol,
+};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::{error::IResult, identification::NodeIdentifier};
+use crate::{
+    error::{IResult, InterplexError},
+    identification::NodeIdentifier,
+};
 
-use super::registrations::Registration;
+use super::{query::Query, record::SignedPeerRecord, registrations::Registration};
 
 /// Request wrapper for rendezvous requests
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -19,38 +27,159 @@ pub struct RendezvousRequest {
 /// Rendezvous command types
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum RendezvousCommand {
-    /// Register this peer in the rendezvous server, replacing existing addresses and updating the TTL
-    Register(Vec<Multiaddr>),
+    /// Register this peer in the rendezvous server with a record of its identity and addresses,
+    /// replacing the existing record and updating the TTL. The requested TTL is clamped to the
+    /// range the server allows, and defaults to the longest one.
+    Register {
+        record: SignedPeerRecord,
+
+        #[serde(default)]
+        ttl: Option<TimeDelta>
+    },
 
     /// De-register the source peer
     Deregister,
 
-    /// Discover all peers in the source's namespace, optionally filtering by group.
-    /// Some peers may or may not be returned, based on their discoverability
-    Discover(Option<String>),
+    /// Discover peers in the source's namespace, optionally filtering by group and by a query
+    /// over their alias and metadata. Peers are only returned if their discoverability allows the
+    /// source to discover them (see [`crate::identification::Discoverability`]).
+    ///
+    /// Results are returned in pages of at most `limit` peers (capped by the server's maximum page
+    /// size). The next page is requested by passing the `next_cursor` of the previous one.
+    Discover {
+        group: Option<String>,
+
+        #[serde(default)]
+        query: Option<Query>,
+        limit: Option<u32>,
+        cursor: Option<String>
+    },
 
-    /// Attempts to retrieve a peer by locator key ("<namespace>/<group>/<id>")
+    /// Attempts to retrieve a peer by locator key ("<namespace>/<group>/<id>"). Peers whose
+    /// discoverability doesn't allow the source to find them are reported as not found.
     Find(String),
 
-    /// Return a list of all groups in the source peer's namespace
-    Groups
+    /// Return a list of the groups in the source peer's namespace, among the peers the source may
+    /// discover
+    Groups,
+
+    /// Subscribe to changes of the peers that a discovery with the same group and filter would
+    /// return. The response holds the first page of the peers covered when the watch is created,
+    /// and the cursor of the next one to pass to [`RendezvousCommand::Discover`]. Later changes
+    /// are pushed to the source over [`WATCH_PROTOCOL`] until it unsubscribes or disconnects.
+    ///
+    /// The ID of the watch is chosen by the source, so that changes pushed before the response
+    /// arrives can be told apart. IDs already in use are rejected.
+    Watch {
+        watch: Uuid,
+        group: Option<String>,
+        filter: Option<Query>
+    },
+
+    /// Cancel a subscription created by [`RendezvousCommand::Watch`]
+    Unwatch(Uuid)
 }
 
 /// Rendezvous response types
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum RendezvousResponse {
-    /// Returned on successful registration, with current expiration time
-    Register(IResult<DateTime<Utc>>),
+    /// Returned on successful registration, with the granted TTL
+    Register(IResult<RegistrationLease>),
 
     /// Returned on successful de-registration
     Deregister(IResult<()>),
 
     /// Returned on successful discovery operation
-    Discover(IResult<Vec<Registration>>),
+    Discover(IResult<DiscoverPage>),
 
     /// Returned on successful find operation (if peer is not found, returns None)
     Find(IResult<Option<Registration>>),
 
     /// Returned on successful group operation
-    Groups(IResult<Vec<String>>)
+    Groups(IResult<Vec<String>>),
+
+    /// Returned on successful watch operation, with the first page of the peers the subscription
+    /// covers
+    Watch(IResult<DiscoverPage>),
+
+    /// Returned on successful unwatch operation
+    Unwatch(IResult<()>)
+}
+
+/// The TTL granted to a registration
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RegistrationLease {
+    pub ttl: TimeDelta,
+
+    /// When the registration expires unless refreshed
+    pub expiration: DateTime<Utc>
+}
+
+/// A page of discovered peers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DiscoverPage {
+    pub registrations: Vec<Registration>,
+
+    /// Cursor of the next page, or None if this is the last one
+    pub next_cursor: Option<String>
+}
+
+impl RendezvousResponse {
+    /// The failed response to a command
+    pub fn failure(command: &RendezvousCommand, error: InterplexError) -> Self {
+        match command {
+            RendezvousCommand::Register { .. } => Self::Register(Err(error)),
+            RendezvousCommand::Deregister => Self::Deregister(Err(error)),
+            RendezvousCommand::Discover { .. } => Self::Discover(Err(error)),
+            RendezvousCommand::Find(_) => Self::Find(Err(error)),
+            RendezvousCommand::Groups => Self::Groups(Err(error)),
+            RendezvousCommand::Watch { .. } => Self::Watch(Err(error)),
+            RendezvousCommand::Unwatch(_) => Self::Unwatch(Err(error)),
+        }
+    }
+}
+
+/// Protocol of rendezvous commands, sent from clients to servers
+pub const RENDEZVOUS_PROTOCOL: StreamProtocol = StreamProtocol::new("/interplex/rendezvous");
+
+/// Protocol of watch notifications, sent from servers to clients
+pub const WATCH_PROTOCOL: StreamProtocol = StreamProtocol::new("/interplex/rendezvous/watch");
+
+/// A change of the peers in the scope of a watch
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum WatchNotification {
+    /// A peer registered, or changed to match the watch
+    Joined(Registration),
+
+    /// A peer refreshed or changed its registration
+    Updated(Registration),
+
+    /// A peer deregistered, or changed to no longer match the watch
+    Left(Registration),
+
+    /// The registration of a peer expired
+    Expired(Registration)
+}
+
+/// Notification pushed to a client, for one of its watches
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WatchRequest {
+    pub watch: Uuid,
+    pub notification: WatchNotification
+}
+
+/// Both rendezvous protocols, which clients and servers each support in one direction
+#[derive(NetworkBehaviour)]
+pub struct Protocols {
+    pub(crate) rendezvous: cbor::Behaviour<RendezvousRequest, RendezvousResponse>,
+    pub(crate) watch: cbor::Behaviour<WatchRequest, ()>,
+}
+
+impl Protocols {
+    pub(crate) fn new(rendezvous: ProtocolSupport, watch: ProtocolSupport) -> Self {
+        Self {
+            rendezvous: cbor::Behaviour::new([(RENDEZVOUS_PROTOCOL, rendezvous)], Config::default()),
+            watch: cbor::Behaviour::new([(WATCH_PROTOCOL, watch)], Config::default()),
+        }
+    }
 }
\ No newline at end of file
@@ -1,4 +1,6 @@
 pub mod message;
 pub mod client;
 pub mod server;
-pub mod registrations;
\ No newline at end of file
+pub mod registrations;
+pub mod record;
+pub mod query;
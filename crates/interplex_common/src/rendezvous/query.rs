@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+use serde_cbor::{value::to_value, Value};
+
+use crate::{
+    error::{IResult, InterplexError},
+    identification::NodeIdentifier,
+};
+
+/// A field of a node's identity that queries can match against
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Field {
+    /// The node's alias
+    Alias,
+
+    /// A metadata entry of the node, by key
+    Metadata(String),
+}
+
+impl From<&str> for Field {
+    fn from(value: &str) -> Self {
+        Self::Metadata(value.to_string())
+    }
+}
+
+impl From<String> for Field {
+    fn from(value: String) -> Self {
+        Self::Metadata(value)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Filter evaluated by the rendezvous server against the alias and metadata of each peer it
+/// discovers. Queries are built from the constructors below, and combined with [`Query::and`] and
+/// [`Query::or`] (ie `Query::equals("role", "worker")?.and(Query::prefix("region", "eu"))`).
+///
+/// A field that is missing, or holds a value of another type, never matches anything but
+/// [`Query::Exists`] (which it fails).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Query {
+    /// The field is equal to the value. Integers and floats are compared by their numeric value.
+    Equals(Field, Value),
+
+    /// The field is set
+    Exists(Field),
+
+    /// The field is a number, and compares to the value as given
+    Compare(Field, Comparison, f64),
+
+    /// The field is a string starting with the prefix
+    Prefix(Field, String),
+
+    /// Every query matches. An empty list matches every node.
+    And(Vec<Query>),
+
+    /// Any query matches. An empty list matches no node.
+    Or(Vec<Query>),
+}
+
+impl Query {
+    pub fn equals(field: impl Into<Field>, value: impl Serialize) -> IResult<Self> {
+        Ok(Self::Equals(
+            field.into(),
+            to_value(value).map_err(InterplexError::serialization)?,
+        ))
+    }
+
+    pub fn exists(field: impl Into<Field>) -> Self {
+        Self::Exists(field.into())
+    }
+
+    pub fn compare(field: impl Into<Field>, comparison: Comparison, value: f64) -> Self {
+        Self::Compare(field.into(), comparison, value)
+    }
+
+    pub fn prefix(field: impl Into<Field>, prefix: impl Into<String>) -> Self {
+        Self::Prefix(field.into(), prefix.into())
+    }
+
+    /// Combines two queries, both of which must match
+    pub fn and(self, other: Query) -> Self {
+        match self {
+            Self::And(mut queries) => {
+                queries.push(other);
+                Self::And(queries)
+            }
+            query => Self::And(vec![query, other]),
+        }
+    }
+
+    /// Combines two queries, either of which must match
+    pub fn or(self, other: Query) -> Self {
+        match self {
+            Self::Or(mut queries) => {
+                queries.push(other);
+                Self::Or(queries)
+            }
+            query => Self::Or(vec![query, other]),
+        }
+    }
+
+    pub fn matches(&self, identity: &NodeIdentifier) -> bool {
+        match self {
+            Self::Equals(field, value) => resolve(identity, field).is_some_and(|found| {
+                match (as_number(&found), as_number(value)) {
+                    (Some(found), Some(value)) => found == value,
+                    _ => &found == value,
+                }
+            }),
+            Self::Exists(field) => resolve(identity, field).is_some(),
+            Self::Compare(field, comparison, value) => resolve(identity, field)
+                .and_then(|found| as_number(&found))
+                .is_some_and(|found| match comparison {
+                    Comparison::Less => found < *value,
+                    Comparison::LessOrEqual => found <= *value,
+                    Comparison::Greater => found > *value,
+                    Comparison::GreaterOrEqual => found >= *value,
+                }),
+            Self::Prefix(field, prefix) => resolve(identity, field)
+                .is_some_and(|found| matches!(found, Value::Text(text) if text.starts_with(prefix))),
+            Self::And(queries) => queries.iter().all(|query| query.matches(identity)),
+            Self::Or(queries) => queries.iter().any(|query| query.matches(identity)),
+        }
+    }
+}
+
+fn resolve(identity: &NodeIdentifier, field: &Field) -> Option<Value> {
+    match field {
+        Field::Alias => identity.alias.clone().map(Value::Text),
+        Field::Metadata(key) => identity.metadata.get(key).cloned(),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Integer(number) => Some(*number as f64),
+        Value::Float(number) => Some(*number),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identification::NodeBuilder;
+
+    fn node() -> NodeIdentifier {
+        NodeBuilder::new("test")
+            .alias("worker-1")
+            .with_meta("role", String::from("worker"))
+            .unwrap()
+            .with_meta("region", String::from("eu-west"))
+            .unwrap()
+            .with_meta("cores", 8)
+            .unwrap()
+            .with_meta("load", 0.5)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn equals() {
+        let node = node();
+        assert!(Query::equals("role", "worker").unwrap().matches(&node));
+        assert!(!Query::equals("role", "manager").unwrap().matches(&node));
+        assert!(Query::equals(Field::Alias, "worker-1").unwrap().matches(&node));
+
+        // Integers and floats compare by value
+        assert!(Query::equals("cores", 8.0).unwrap().matches(&node));
+        assert!(Query::equals("load", 0.5).unwrap().matches(&node));
+
+        // Values of another type, and missing fields, never match
+        assert!(!Query::equals("cores", "8").unwrap().matches(&node));
+        assert!(!Query::equals("missing", "worker").unwrap().matches(&node));
+    }
+
+    #[test]
+    fn exists() {
+        let node = node();
+        assert!(Query::exists("role").matches(&node));
+        assert!(Query::exists(Field::Alias).matches(&node));
+        assert!(!Query::exists("missing").matches(&node));
+
+        let anonymous = NodeBuilder::new("test").build().unwrap();
+        assert!(!Query::exists(Field::Alias).matches(&anonymous));
+    }
+
+    #[test]
+    fn compare() {
+        let node = node();
+        assert!(Query::compare("cores", Comparison::Greater, 4.0).matches(&node));
+        assert!(Query::compare("cores", Comparison::GreaterOrEqual, 8.0).matches(&node));
+        assert!(!Query::compare("cores", Comparison::Less, 8.0).matches(&node));
+        assert!(Query::compare("load", Comparison::LessOrEqual, 0.5).matches(&node));
+        assert!(!Query::compare("role", Comparison::Greater, 0.0).matches(&node));
+        assert!(!Query::compare("missing", Comparison::Less, 100.0).matches(&node));
+    }
+
+    #[test]
+    fn prefix() {
+        let node = node();
+        assert!(Query::prefix("region", "eu").matches(&node));
+        assert!(!Query::prefix("region", "us").matches(&node));
+        assert!(Query::prefix(Field::Alias, "worker-").matches(&node));
+        assert!(!Query::prefix("cores", "8").matches(&node));
+    }
+
+    #[test]
+    fn and_or() {
+        let node = node();
+        let worker = Query::equals("role", "worker").unwrap();
+        let manager = Query::equals("role", "manager").unwrap();
+        let european = Query::prefix("region", "eu");
+
+        assert!(worker.clone().and(european.clone()).matches(&node));
+        assert!(!manager.clone().and(european.clone()).matches(&node));
+        assert!(manager.clone().or(european).matches(&node));
+        assert!(!manager.clone().or(Query::exists("missing")).matches(&node));
+
+        // Chained combinators flatten into a single list
+        assert_eq!(
+            worker.clone().and(worker.clone()).and(manager.clone()),
+            Query::And(vec![worker.clone(), worker, manager])
+        );
+
+        assert!(Query::And(Vec::new()).matches(&node));
+        assert!(!Query::Or(Vec::new()).matches(&node));
+    }
+}
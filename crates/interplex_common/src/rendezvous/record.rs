@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use libp2p::{core::SignedEnvelope, identity::Keypair, Multiaddr};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{IResult, InterplexError},
+    identification::NodeIdentifier,
+};
+
+/// Domain separation string of peer record signatures
+const RECORD_DOMAIN: &str = "interplex-peer-record";
+
+/// Payload type of peer record envelopes
+const RECORD_PAYLOAD_TYPE: &[u8] = b"/interplex/peer-record";
+
+/// Identity and addresses of a node, as stated by the node itself
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PeerRecord {
+    pub identity: NodeIdentifier,
+    pub addresses: Vec<Multiaddr>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A [`PeerRecord`] in a libp2p signed envelope, signed with the key of the node it describes.
+///
+/// Records are relayed by rendezvous servers as is, so clients can check that a registration
+/// wasn't altered by the server.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedPeerRecord(#[serde(with = "serde_bytes")] Vec<u8>);
+
+impl SignedPeerRecord {
+    pub fn new(keypair: &Keypair, record: &PeerRecord) -> IResult<Self> {
+        let payload = serde_cbor::to_vec(record).map_err(InterplexError::serialization)?;
+        let envelope = SignedEnvelope::new(
+            keypair,
+            String::from(RECORD_DOMAIN),
+            RECORD_PAYLOAD_TYPE.to_vec(),
+            payload,
+        )
+        .map_err(|e| InterplexError::InvalidRecord(e.to_string()))?;
+
+        Ok(Self(envelope.into_protobuf_encoding()))
+    }
+
+    /// Checks the signature of the record, and that it was signed by the node it describes
+    pub fn verify(&self) -> IResult<PeerRecord> {
+        let envelope = SignedEnvelope::from_protobuf_encoding(&self.0)
+            .map_err(|e| InterplexError::InvalidRecord(e.to_string()))?;
+        let (payload, key) = envelope
+            .payload_and_signing_key(String::from(RECORD_DOMAIN), RECORD_PAYLOAD_TYPE)
+            .map_err(|e| InterplexError::InvalidRecord(e.to_string()))?;
+        let record: PeerRecord =
+            serde_cbor::from_slice(payload).map_err(InterplexError::deserialization)?;
+
+        if key.to_peer_id() != record.identity.peer_id {
+            return Err(InterplexError::InvalidRecord(format!(
+                "Record of {} was signed by {}",
+                record.identity.peer_id,
+                key.to_peer_id()
+            )));
+        }
+
+        Ok(record)
+    }
+}
@@ -1,9 +1,9 @@
-use std::{collections::HashSet, fs::create_dir_all, path::Path};
+use std::{borrow::Cow, collections::HashSet, fs::create_dir_all, marker::PhantomData, ops::Bound, path::Path};
 
 use chrono::{DateTime, TimeDelta, Utc};
 use heed::{
-    types::{SerdeBincode, Str},
-    Database, Env, EnvOpenOptions, RoTxn, RwTxn,
+    types::Str,
+    BoxedError, BytesDecode, BytesEncode, Database, Env, EnvOpenOptions, RoTxn, RwTxn,
 };
 use libp2p::Multiaddr;
 use serde::{Deserialize, Serialize};
@@ -13,18 +13,129 @@ use crate::{
     identification::{Discoverability, NodeIdentifier},
 };
 
+use super::{
+    query::Query,
+    record::{PeerRecord, SignedPeerRecord},
+};
+
+/// Largest difference tolerated between the clock of a node, which dates its records, and the
+/// clocks of rendezvous servers and other nodes
+pub const MAX_CLOCK_SKEW: TimeDelta = TimeDelta::minutes(5);
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Registration {
     pub identity: NodeIdentifier,
     pub addresses: Vec<Multiaddr>,
     pub last_registration: DateTime<Utc>,
-    pub ttl: TimeDelta
+    pub ttl: TimeDelta,
+
+    /// Record the node registered with, signed by the node itself
+    pub record: SignedPeerRecord
 }
 
 impl Registration {
     pub fn expiration(&self) -> DateTime<Utc> {
         self.last_registration + self.ttl
     }
+
+    pub fn expired(&self) -> bool {
+        self.expiration() <= Utc::now()
+    }
+
+    /// Key of the registration in the expiration index, which sorts by expiration
+    fn expiration_key(&self) -> String {
+        format!("{:020}:{}", self.expiration().timestamp_millis(), self.identity.key())
+    }
+
+    /// Verifies the signed record of the registration, and returns the registration with the
+    /// identity and addresses taken from the record rather than from the server
+    pub fn verified(&self) -> IResult<Self> {
+        let record = self.record.verify()?;
+        if record.identity.peer_id != self.identity.peer_id {
+            return Err(InterplexError::InvalidRecord(format!(
+                "Registration of {} carries the record of {}",
+                self.identity.peer_id, record.identity.peer_id
+            )));
+        }
+
+        // The record is signed right before registering, so a record from after the registration
+        // or older than its TTL is one the server kept serving after it was replaced. Records are
+        // dated by the node and registrations by the server, so their clocks may differ a little.
+        if record.timestamp - self.last_registration > MAX_CLOCK_SKEW
+            || self.last_registration - record.timestamp > self.ttl + MAX_CLOCK_SKEW
+        {
+            return Err(InterplexError::InvalidRecord(format!(
+                "Record of {} from {} doesn't match its registration at {}",
+                record.identity.peer_id, record.timestamp, self.last_registration
+            )));
+        }
+
+        Ok(Self {
+            identity: record.identity,
+            addresses: record.addresses,
+            ..self.clone()
+        })
+    }
+
+    /// Whether `node` may discover this registration, based on its discoverability. Nodes never
+    /// discover themselves.
+    pub fn discoverable_by(&self, node: &NodeIdentifier) -> bool {
+        self.identity.key() != node.key()
+            && !matches!(self.identity.discoverability, Discoverability::Direct)
+            && self.visible_to(node)
+    }
+
+    /// Whether `node` may find this registration by its key, based on its discoverability. Nodes
+    /// may always find themselves.
+    pub fn findable_by(&self, node: &NodeIdentifier) -> bool {
+        self.identity.peer_id == node.peer_id || self.visible_to(node)
+    }
+
+    fn visible_to(&self, node: &NodeIdentifier) -> bool {
+        self.identity.namespace == node.namespace
+            && match &self.identity.discoverability {
+                Discoverability::Namespace | Discoverability::Direct => true,
+                Discoverability::Group => self.identity.group() == node.group(),
+                Discoverability::Private { peers, groups } => {
+                    peers.contains(&node.peer_id) || groups.contains(&node.group())
+                }
+            }
+    }
+}
+
+/// Stores values as CBOR. Unlike bincode, CBOR is self-describing, which the CBOR values of node
+/// metadata need to be read back.
+struct SerdeCbor<T>(PhantomData<T>);
+
+impl<'a, T: Serialize + 'a> BytesEncode<'a> for SerdeCbor<T> {
+    type EItem = T;
+
+    fn bytes_encode(item: &'a Self::EItem) -> Result<Cow<'a, [u8]>, BoxedError> {
+        serde_cbor::to_vec(item).map(Cow::Owned).map_err(Into::into)
+    }
+}
+
+impl<'a, T: Deserialize<'a> + 'a> BytesDecode<'a> for SerdeCbor<T> {
+    type DItem = T;
+
+    fn bytes_decode(bytes: &'a [u8]) -> Result<Self::DItem, BoxedError> {
+        serde_cbor::from_slice(bytes).map_err(Into::into)
+    }
+}
+
+/// Reads the registration stored under `key`. Registrations that can't be decoded, such as those
+/// written in bincode by earlier versions, are treated as missing, so that registering again
+/// replaces them rather than failing.
+fn stored_registration(
+    db: &Database<Str, SerdeCbor<Registration>>,
+    txn: &RoTxn<'_>,
+    key: &str,
+) -> IResult<Option<Registration>> {
+    match db.get(txn, key) {
+        Ok(registration) => Ok(registration),
+        Err(heed::Error::Decoding(_)) => Ok(None),
+        Err(e) => Err(InterplexError::wrap(e)),
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -65,10 +176,10 @@ impl Registrations {
     fn registrations_read_only(
         &self,
         txn: &RoTxn<'_>
-    ) -> IResult<Database<Str, SerdeBincode<Registration>>> {
+    ) -> IResult<Database<Str, SerdeCbor<Registration>>> {
         let db = self
             .0
-            .open_database::<Str, SerdeBincode<Registration>>(txn, Some("registrations"))
+            .open_database::<Str, SerdeCbor<Registration>>(txn, Some("registrations"))
             .or_else(|e| Err(InterplexError::wrap(e)))?
             .ok_or(InterplexError::wrap(
                 "Registration database not initialized.",
@@ -79,10 +190,10 @@ impl Registrations {
     fn registrations_read_write(
         &self,
         txn: &mut RwTxn<'_>
-    ) -> IResult<Database<Str, SerdeBincode<Registration>>> {
+    ) -> IResult<Database<Str, SerdeCbor<Registration>>> {
         let db = self
             .0
-            .create_database::<Str, SerdeBincode<Registration>>(txn, Some("registrations"))
+            .create_database::<Str, SerdeCbor<Registration>>(txn, Some("registrations"))
             .or_else(|e| Err(InterplexError::wrap(e)))?;
         Ok(db)
     }
@@ -104,12 +215,16 @@ impl Registrations {
         Ok(db)
     }
 
+    /// Creates or refreshes the registration of a node for `ttl` from its verified record,
+    /// returning it along with the registration it replaced. Records that aren't newer than the
+    /// one already registered are rejected.
     pub fn register(
         &self,
-        node: NodeIdentifier,
-        addresses: Vec<Multiaddr>,
+        record: PeerRecord,
+        signed: SignedPeerRecord,
         ttl: TimeDelta
-    ) -> IResult<Registration> {
+    ) -> IResult<(Registration, Option<Registration>)> {
+        let PeerRecord { identity: node, addresses, timestamp } = record;
         let mut rw = self.rw()?;
         let ro = self.ro()?;
 
@@ -117,139 +232,186 @@ impl Registrations {
         let edb = self.expirations_read_write(&mut rw)?;
 
         let current_time = Utc::now();
-        let (created, last_exp) = if let Some(mut reg) = rdb
-            .get(&ro, &node.key())
-            .or_else(|e| Err(InterplexError::wrap(e)))?
-        {
+
+        // Clients accept records within the same margin of the registration time, so a node whose
+        // clock is too far off is told here rather than being silently left out of discoveries
+        if (timestamp - current_time).abs() > MAX_CLOCK_SKEW {
+            return Err(InterplexError::InvalidRecord(format!(
+                "Record of {} from {timestamp} is too far from the server time {current_time}",
+                node.peer_id
+            )));
+        }
+
+        let previous = stored_registration(&rdb, &ro, &node.key())?;
+        if let Some(stored) = previous.as_ref().and_then(|reg| reg.record.verify().ok()) {
+            if timestamp <= stored.timestamp {
+                return Err(InterplexError::InvalidRecord(format!(
+                    "Record of {} from {timestamp} isn't newer than the registered one from {}",
+                    node.peer_id, stored.timestamp
+                )));
+            }
+        }
+
+        let created = if let Some(mut reg) = previous.clone() {
             reg.addresses = addresses.clone();
             reg.identity.discoverability = node.clone().discoverability;
             reg.identity.alias = node.clone().alias;
             reg.identity.metadata = node.clone().metadata;
-            let last_exp = reg.last_registration.timestamp();
             reg.last_registration = current_time;
             reg.ttl = ttl.clone();
+            reg.record = signed;
 
-            (reg, Some(last_exp))
+            reg
         } else {
-            (
-                Registration {
-                    identity: node.clone(),
-                    addresses: addresses.clone(),
-                    last_registration: current_time,
-                    ttl: ttl.clone()
-                },
-                None,
-            )
+            Registration {
+                identity: node.clone(),
+                addresses: addresses.clone(),
+                last_registration: current_time,
+                ttl: ttl.clone(),
+                record: signed
+            }
         };
 
-        if let Some(exp) = last_exp {
-            edb.delete(&mut rw, &format!("{}:{}", exp, node.clone().key()))
-                .or_else(|e| Err(InterplexError::wrap(e)))?;
+        if let Some(previous) = previous.as_ref() {
+            edb.delete(&mut rw, &previous.expiration_key())
+                .map_err(InterplexError::wrap)?;
         }
 
-        edb.put(
-            &mut rw,
-            &format!("{}:{}", current_time.timestamp(), node.clone().key()),
-            &node.clone().key(),
-        )
-        .or_else(|e| Err(InterplexError::wrap(e)))?;
+        edb.put(&mut rw, &created.expiration_key(), &created.identity.key())
+            .map_err(InterplexError::wrap)?;
         rdb.put(&mut rw, &created.identity.key(), &created)
             .or_else(|e| Err(InterplexError::wrap(e)))?;
         rw.commit().or_else(|e| Err(InterplexError::wrap(e)))?;
         ro.commit().or_else(|e| Err(InterplexError::wrap(e)))?;
-        Ok(created.clone())
+        Ok((created, previous))
     }
 
-    pub fn deregister(&self, node: NodeIdentifier) -> IResult<()> {
+    /// Removes the registration of a node, returning it if there was one
+    pub fn deregister(&self, node: NodeIdentifier) -> IResult<Option<Registration>> {
         let mut rw = self.rw()?;
 
         let rdb = self.registrations_read_write(&mut rw)?;
+        let edb = self.expirations_read_write(&mut rw)?;
+        let removed = stored_registration(&rdb, &rw, &node.key())?;
+        if let Some(removed) = removed.as_ref() {
+            edb.delete(&mut rw, &removed.expiration_key())
+                .map_err(InterplexError::wrap)?;
+        }
         rdb.delete(&mut rw, &node.key())
             .or_else(|e| Err(InterplexError::wrap(e)))?;
         rw.commit().or_else(|e| Err(InterplexError::wrap(e)))?;
-        Ok(())
+        Ok(removed)
     }
 
-    pub fn poll(&self, expiration: TimeDelta) -> IResult<Option<Registration>> {
+    /// Removes and returns the first registration that has expired, if any
+    pub fn poll(&self) -> IResult<Option<Registration>> {
         let mut rw = self.rw()?;
-        let ro = self.ro()?;
-
-        let rdb = self.registrations_read_write(&mut rw)?;
         let edb = self.expirations_read_write(&mut rw)?;
-        let expired = if let Ok(Some((key, value))) = edb.first(&ro) {
-            if let Some((last_reg, _)) = key.split_once(":") {
-                if let Ok(ts) = last_reg.parse::<i64>() {
-                    if DateTime::from_timestamp(ts, 0).unwrap() + expiration < Utc::now() {
-                        Some((key.to_string(), value.to_string()))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            } else {
-                None
+        let rdb = self.registrations_read_write(&mut rw)?;
+        let now = Utc::now().timestamp_millis();
+
+        let mut expired = None;
+        while let Some((key, value)) = edb
+            .first(&rw)
+            .map_err(InterplexError::wrap)?
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        {
+            let due = key
+                .split_once(":")
+                .and_then(|(timestamp, _)| timestamp.parse::<i64>().ok());
+            if due.is_some_and(|due| due > now) {
+                break;
             }
-        } else {
-            None
-        };
 
+            edb.delete(&mut rw, &key).map_err(InterplexError::wrap)?;
 
-        if let Some((expired_key, expired_value)) = expired {
-            let _ = edb.delete(&mut rw, &expired_key);
-            if let Ok(Some(reg)) = rdb.get(&ro, &expired_value) {
-                let _ = rdb.delete(&mut rw, &expired_key);
-                let _ = rw.commit();
-                let _ = ro.commit();
-                return Ok(Some(reg));
+            // Entries left behind by a registration that was since refreshed are skipped
+            if let Ok(Some(reg)) = rdb.get(&rw, &value) {
+                if reg.expiration_key() == key {
+                    rdb.delete(&mut rw, &value).map_err(InterplexError::wrap)?;
+                    expired = Some(reg);
+                    break;
+                }
             }
         }
 
+        rw.commit().map_err(InterplexError::wrap)?;
+        Ok(expired)
+    }
+
+    /// When the next registration expires
+    pub fn next_expiration(&self) -> IResult<Option<DateTime<Utc>>> {
+        let ro = self.ro()?;
+        let edb = self.expirations_read_only(&ro)?;
+        let next = edb
+            .first(&ro)
+            .map_err(InterplexError::wrap)?
+            .and_then(|(key, _)| key.split_once(":").and_then(|(timestamp, _)| timestamp.parse::<i64>().ok()))
+            .and_then(DateTime::from_timestamp_millis);
         let _ = ro.commit();
-        let _ = rw.commit();
-        Ok(None)
+        Ok(next)
     }
 
+    /// Returns up to `limit` registrations visible to `node` and matching `query`, in key order,
+    /// starting after the `cursor` key. The cursor of the next page is returned along with them
+    /// if more remain. Cursors outside the namespace and group being discovered are rejected.
     pub fn discover(
         &self,
         node: NodeIdentifier,
         group: Option<impl AsRef<str>>,
-    ) -> IResult<Vec<Registration>> {
+        query: Option<&Query>,
+        limit: usize,
+        cursor: Option<String>,
+    ) -> IResult<(Vec<Registration>, Option<String>)> {
         let ro = self.ro()?;
         let rdb = self.registrations_read_only(&ro)?;
         let prefix = match group {
             Some(g) => format!("{}/{}/", node.namespace.clone(), g.as_ref().to_string()),
             None => format!("{}/", node.namespace.clone()),
         };
+        let start = match cursor.as_deref() {
+            Some(cursor) if cursor.starts_with(&prefix) => Bound::Excluded(cursor),
+            Some(cursor) => return Err(InterplexError::InvalidCursor(cursor.to_string())),
+            None => Bound::Included(prefix.as_str()),
+        };
+
         let mut discovered: Vec<Registration> = Vec::new();
+        let mut next_cursor = None;
         for result in rdb
-            .prefix_iter(&ro, &prefix)
+            .range(&ro, &(start, Bound::Unbounded))
             .or_else(|e| Err(InterplexError::wrap(e)))?
         {
             if let Ok((key, registration)) = result {
-                if node.key() != key.to_string() {
-                    match registration.identity.discoverability {
-                        Discoverability::Namespace => {
-                            discovered.push(registration.clone());
-                        }
-                        Discoverability::Group => {
-                            if registration.identity.group() == node.group() {
-                                discovered.push(registration.clone());
-                            }
-                        },
-                        _ => ()
+                if !key.starts_with(&prefix) {
+                    break;
+                }
+
+                // Expired registrations are hidden until they are removed
+                if !registration.expired()
+                    && registration.discoverable_by(&node)
+                    && query.is_none_or(|query| query.matches(&registration.identity))
+                {
+                    if discovered.len() == limit {
+                        next_cursor = discovered.last().map(|last| last.identity.key());
+                        break;
                     }
+
+                    discovered.push(registration);
                 }
             }
         }
         let _ = ro.commit();
-        Ok(discovered)
+        Ok((discovered, next_cursor))
     }
 
-    pub fn get(&self, key: impl Into<String>) -> IResult<Option<Registration>> {
+    /// Returns the registration stored under `key`, if `node` may find it
+    pub fn get(&self, node: &NodeIdentifier, key: impl Into<String>) -> IResult<Option<Registration>> {
         let ro = self.ro()?;
         let rdb = self.registrations_read_only(&ro)?;
-        if let Ok(Some(result)) = rdb.get(&ro, &key.into()) {
+        if let Ok(Some(result)) = rdb
+            .get(&ro, &key.into())
+            .map(|result| result.filter(|reg| !reg.expired() && reg.findable_by(node)))
+        {
             let _ = ro.commit();
             Ok(Some(result))
         } else {
@@ -258,16 +420,430 @@ impl Registrations {
         }
     }
 
-    pub fn groups(&self, namespace: impl Into<String>) -> IResult<Vec<String>> {
+    /// Returns the groups of the registrations in `node`'s namespace that `node` may discover, so
+    /// that hidden registrations don't reveal their group
+    pub fn groups(&self, node: &NodeIdentifier) -> IResult<Vec<String>> {
         let ro = self.ro()?;
         let rdb = self.registrations_read_only(&ro)?;
         let mut groups: HashSet<String> = HashSet::new();
-        for registration in rdb.prefix_iter(&ro, &format!("{}/", namespace.into())).or_else(|e| Err(InterplexError::wrap(e)))? {
-            if let Ok((_, Registration {identity: NodeIdentifier {group: Some(group), ..}, ..})) = registration {
-                groups.insert(group);
+        for (_, registration) in rdb
+            .prefix_iter(&ro, &format!("{}/", node.namespace))
+            .map_err(InterplexError::wrap)?
+            .flatten()
+        {
+            if !registration.expired() && registration.discoverable_by(node) {
+                if let Some(group) = registration.identity.group {
+                    groups.insert(group);
+                }
             }
         }
         let _ = ro.commit();
         Ok(groups.into_iter().collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use libp2p::{identity::Keypair, PeerId};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::identification::NodeBuilder;
+
+    fn identity(keypair: &Keypair) -> NodeIdentifier {
+        NodeBuilder::new_from_id("test", keypair.public().to_peer_id())
+            .build()
+            .unwrap()
+    }
+
+    fn record(
+        signer: &Keypair,
+        identity: NodeIdentifier,
+        timestamp: DateTime<Utc>,
+    ) -> (PeerRecord, SignedPeerRecord) {
+        let record = PeerRecord {
+            identity,
+            addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+            timestamp,
+        };
+        let signed = SignedPeerRecord::new(signer, &record).unwrap();
+        (record, signed)
+    }
+
+    fn registration(
+        keypair: &Keypair,
+        timestamp: DateTime<Utc>,
+        last_registration: DateTime<Utc>,
+    ) -> Registration {
+        let (record, signed) = record(keypair, identity(keypair), timestamp);
+        Registration {
+            identity: record.identity,
+            addresses: Vec::new(),
+            last_registration,
+            ttl: TimeDelta::minutes(10),
+            record: signed,
+        }
+    }
+
+    /// Opens a store in a fresh temporary folder, removed when the test ends
+    struct TempStore(Registrations, std::path::PathBuf);
+
+    impl TempStore {
+        fn new() -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("interplex-registrations-{}", Uuid::new_v4()));
+            Self(Registrations::new(&path), path)
+        }
+    }
+
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.1);
+        }
+    }
+
+    #[test]
+    fn verified_takes_identity_and_addresses_from_record() {
+        let keypair = Keypair::generate_ed25519();
+        let now = Utc::now();
+        let verified = registration(&keypair, now - TimeDelta::seconds(1), now)
+            .verified()
+            .unwrap();
+
+        assert_eq!(verified.identity.peer_id, keypair.public().to_peer_id());
+        assert_eq!(
+            verified.addresses,
+            vec!["/ip4/127.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn verified_rejects_record_of_another_signer() {
+        let keypair = Keypair::generate_ed25519();
+        let forger = Keypair::generate_ed25519();
+        let now = Utc::now();
+        let mut registration = registration(&keypair, now, now);
+        registration.record = record(&forger, identity(&keypair), now).1;
+
+        assert!(matches!(registration.verified(), Err(InterplexError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn verified_rejects_stale_records() {
+        let keypair = Keypair::generate_ed25519();
+        let now = Utc::now();
+        let ttl = TimeDelta::minutes(10);
+
+        let outlived = registration(&keypair, now - ttl - MAX_CLOCK_SKEW - TimeDelta::seconds(1), now);
+        assert!(matches!(outlived.verified(), Err(InterplexError::InvalidRecord(_))));
+
+        let future = registration(&keypair, now + MAX_CLOCK_SKEW + TimeDelta::seconds(1), now);
+        assert!(matches!(future.verified(), Err(InterplexError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn verified_tolerates_clock_skew() {
+        let keypair = Keypair::generate_ed25519();
+        let now = Utc::now();
+        let ttl = TimeDelta::minutes(10);
+
+        let ahead = registration(&keypair, now + MAX_CLOCK_SKEW - TimeDelta::seconds(1), now);
+        assert!(ahead.verified().is_ok());
+
+        let behind = registration(&keypair, now - ttl - MAX_CLOCK_SKEW + TimeDelta::seconds(1), now);
+        assert!(behind.verified().is_ok());
+    }
+
+    #[test]
+    fn register_rejects_records_too_far_from_server_time() {
+        let store = TempStore::new();
+        let keypair = Keypair::generate_ed25519();
+        let now = Utc::now();
+
+        for skew in [MAX_CLOCK_SKEW, -MAX_CLOCK_SKEW] {
+            let skew = skew + skew / 10;
+            let (skewed, skewed_signed) = record(&keypair, identity(&keypair), now + skew);
+            assert!(matches!(
+                store.0.register(skewed, skewed_signed, TimeDelta::minutes(10)),
+                Err(InterplexError::InvalidRecord(_))
+            ));
+        }
+
+        let (ahead, ahead_signed) = record(&keypair, identity(&keypair), now + TimeDelta::minutes(1));
+        assert!(store.0.register(ahead, ahead_signed, TimeDelta::minutes(10)).is_ok());
+    }
+
+    #[test]
+    fn register_rejects_records_not_newer_than_registered() {
+        let store = TempStore::new();
+        let keypair = Keypair::generate_ed25519();
+        let now = Utc::now();
+        let (first, first_signed) = record(&keypair, identity(&keypair), now);
+        store.0.register(first.clone(), first_signed.clone(), TimeDelta::minutes(10)).unwrap();
+
+        assert!(matches!(
+            store.0.register(first, first_signed, TimeDelta::minutes(10)),
+            Err(InterplexError::InvalidRecord(_))
+        ));
+
+        let (older, older_signed) = record(&keypair, identity(&keypair), now - TimeDelta::seconds(1));
+        assert!(store.0.register(older, older_signed, TimeDelta::minutes(10)).is_err());
+
+        let (newer, newer_signed) = record(&keypair, identity(&keypair), now + TimeDelta::seconds(1));
+        let (_, previous) = store.0.register(newer, newer_signed, TimeDelta::minutes(10)).unwrap();
+        assert!(previous.is_some());
+    }
+
+    #[test]
+    fn reads_back_metadata_values() {
+        let store = TempStore::new();
+        let keypair = Keypair::generate_ed25519();
+        let mut node = identity(&keypair);
+        node.metadata
+            .insert(String::from("role"), serde_cbor::Value::Text(String::from("worker")));
+        node.discoverability = Discoverability::Namespace;
+        let key = node.key();
+        let (record, signed) = record(&keypair, node.clone(), Utc::now());
+        store.0.register(record, signed, TimeDelta::minutes(10)).unwrap();
+
+        let found = store.0.get(&node, key).unwrap().unwrap();
+        assert_eq!(
+            found.identity.metadata.get("role"),
+            Some(&serde_cbor::Value::Text(String::from("worker")))
+        );
+    }
+
+    #[test]
+    fn register_replaces_undecodable_registrations() {
+        let store = TempStore::new();
+        let keypair = Keypair::generate_ed25519();
+        let node = identity(&keypair);
+        {
+            let Registrations(env) = &store.0;
+            let mut rw = store.0.rw().unwrap();
+            let raw = env
+                .create_database::<Str, heed::types::Bytes>(&mut rw, Some("registrations"))
+                .unwrap();
+            raw.put(&mut rw, &node.key(), b"not a registration").unwrap();
+            rw.commit().unwrap();
+        }
+
+        let (record, signed) = record(&keypair, node, Utc::now());
+        let (_, previous) = store.0.register(record, signed, TimeDelta::minutes(10)).unwrap();
+        assert!(previous.is_none());
+    }
+
+    fn node(peer_id: PeerId, namespace: &str, group: &str, mode: Discoverability) -> NodeIdentifier {
+        NodeBuilder::new_from_id(namespace, peer_id)
+            .group(group)
+            .discoverability(mode)
+            .build()
+            .unwrap()
+    }
+
+    /// A registration of `identity`, whose record is irrelevant to visibility
+    fn registration_of(identity: NodeIdentifier) -> Registration {
+        let signed = record(&Keypair::generate_ed25519(), identity.clone(), Utc::now()).1;
+        Registration {
+            identity,
+            addresses: Vec::new(),
+            last_registration: Utc::now(),
+            ttl: TimeDelta::minutes(10),
+            record: signed,
+        }
+    }
+
+    /// Checks `discoverable_by` and `findable_by` of a node in group "team" against each kind of
+    /// requester, in the order: itself, a listed peer, a node of a listed group, a node of the same
+    /// group, a stranger and a node of another namespace
+    fn check(mode: impl Fn(PeerId) -> Discoverability, discover: [bool; 6], find: [bool; 6]) {
+        let owner = PeerId::random();
+        let listed = PeerId::random();
+        let mode = mode(listed);
+        let registration = registration_of(node(owner, "test", "team", mode.clone()));
+        let requesters = [
+            node(owner, "test", "team", mode),
+            node(listed, "test", "other", Discoverability::Namespace),
+            node(PeerId::random(), "test", "ops", Discoverability::Namespace),
+            node(PeerId::random(), "test", "team", Discoverability::Namespace),
+            node(PeerId::random(), "test", "other", Discoverability::Namespace),
+            node(listed, "elsewhere", "ops", Discoverability::Namespace),
+        ];
+
+        let discovered = requesters.each_ref().map(|node| registration.discoverable_by(node));
+        let found = requesters.each_ref().map(|node| registration.findable_by(node));
+        assert_eq!(discovered, discover, "discover");
+        assert_eq!(found, find, "find");
+    }
+
+    #[test]
+    fn visibility_namespace() {
+        check(
+            |_| Discoverability::Namespace,
+            [false, true, true, true, true, false],
+            [true, true, true, true, true, false],
+        );
+    }
+
+    #[test]
+    fn visibility_group() {
+        check(
+            |_| Discoverability::Group,
+            [false, false, false, true, false, false],
+            [true, false, false, true, false, false],
+        );
+    }
+
+    #[test]
+    fn visibility_direct() {
+        check(
+            |_| Discoverability::Direct,
+            [false; 6],
+            [true, true, true, true, true, false],
+        );
+    }
+
+    #[test]
+    fn visibility_private() {
+        check(
+            |listed| Discoverability::Private {
+                peers: vec![listed],
+                groups: vec![String::from("ops")],
+            },
+            [false, true, true, false, false, false],
+            [true, true, true, false, false, false],
+        );
+    }
+
+    #[test]
+    fn groups_only_lists_discoverable_registrations() {
+        let store = TempStore::new();
+        let stranger = node(PeerId::random(), "test", "other", Discoverability::Namespace);
+        let listed = node(PeerId::random(), "test", "other", Discoverability::Namespace);
+        let modes = [
+            ("open", Discoverability::Namespace),
+            ("hidden", Discoverability::Direct),
+            ("secret", Discoverability::Private {
+                peers: vec![listed.peer_id],
+                groups: Vec::new(),
+            }),
+        ];
+        for (group, mode) in modes {
+            let keypair = Keypair::generate_ed25519();
+            let identity = node(keypair.public().to_peer_id(), "test", group, mode);
+            let (record, signed) = record(&keypair, identity, Utc::now());
+            store.0.register(record, signed, TimeDelta::minutes(10)).unwrap();
+        }
+
+        let sorted = |mut groups: Vec<String>| {
+            groups.sort();
+            groups
+        };
+        assert_eq!(store.0.groups(&stranger).unwrap(), vec![String::from("open")]);
+        assert_eq!(
+            sorted(store.0.groups(&listed).unwrap()),
+            vec![String::from("open"), String::from("secret")]
+        );
+    }
+
+    /// Registers `count` discoverable nodes in `namespace`, returning their keys in key order
+    fn register_nodes(
+        store: &Registrations,
+        namespace: &str,
+        group: &str,
+        count: usize,
+    ) -> Vec<String> {
+        let mut keys: Vec<String> = (0..count)
+            .map(|_| {
+                let keypair = Keypair::generate_ed25519();
+                let node = node(
+                    keypair.public().to_peer_id(),
+                    namespace,
+                    group,
+                    Discoverability::Namespace,
+                );
+                let (record, signed) = record(&keypair, node.clone(), Utc::now());
+                store.register(record, signed, TimeDelta::minutes(10)).unwrap();
+                node.key()
+            })
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn discover_walks_every_page_once() {
+        let store = TempStore::new();
+        let mut keys = register_nodes(&store.0, "test", "team", 4);
+        keys.extend(register_nodes(&store.0, "test", "ops", 3));
+        keys.sort();
+        register_nodes(&store.0, "other", "team", 2);
+        let requester = node(PeerId::random(), "test", "team", Discoverability::Namespace);
+
+        for limit in [1, 3, 7, 10] {
+            let mut walked = Vec::new();
+            let mut pages = 0;
+            let mut cursor = None;
+            loop {
+                let (page, next) = store
+                    .0
+                    .discover(requester.clone(), None::<&str>, None, limit, cursor)
+                    .unwrap();
+                assert!(page.len() <= limit);
+                walked.extend(page.into_iter().map(|registration| registration.identity.key()));
+                pages += 1;
+                match next {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+
+            assert_eq!(walked, keys, "limit {limit}");
+            assert_eq!(pages, keys.len().div_ceil(limit), "limit {limit}");
+        }
+    }
+
+    #[test]
+    fn discover_pages_within_group() {
+        let store = TempStore::new();
+        let keys = register_nodes(&store.0, "test", "team", 3);
+        register_nodes(&store.0, "test", "ops", 3);
+        let requester = node(PeerId::random(), "test", "team", Discoverability::Namespace);
+
+        let (first, cursor) = store
+            .0
+            .discover(requester.clone(), Some("team"), None, 2, None)
+            .unwrap();
+        let (second, cursor) = store
+            .0
+            .discover(requester, Some("team"), None, 2, cursor)
+            .unwrap();
+        let walked: Vec<String> = first
+            .into_iter()
+            .chain(second)
+            .map(|registration| registration.identity.key())
+            .collect();
+        assert_eq!(walked, keys);
+        assert!(cursor.is_none());
+    }
+
+    #[test]
+    fn discover_rejects_cursors_outside_range() {
+        let store = TempStore::new();
+        let team = register_nodes(&store.0, "test", "team", 2);
+        let other = register_nodes(&store.0, "other", "team", 2);
+        let requester = node(PeerId::random(), "test", "team", Discoverability::Namespace);
+
+        // Keys of another namespace, or of another group than the one discovered
+        for (group, cursor) in [(None, &other[0]), (Some("ops"), &team[0])] {
+            assert!(matches!(
+                store.0.discover(requester.clone(), group, None, 10, Some(cursor.clone())),
+                Err(InterplexError::InvalidCursor(_))
+            ));
+        }
+        assert!(matches!(
+            store.0.discover(requester, None::<&str>, None, 10, Some(String::from("garbage"))),
+            Err(InterplexError::InvalidCursor(_))
+        ));
+    }
+}
@@ -1,16 +1,25 @@
-use std::task::{Context, Poll};
+use std::{
+    collections::HashMap,
+    task::{Context, Poll},
+};
 
 use crate::{error::InterplexError, identification::NodeIdentifier};
-use chrono::TimeDelta;
+use chrono::{DateTime, TimeDelta, Utc};
 use derive_builder::Builder;
 use libp2p::{
-    request_response::{self, ProtocolSupport},
-    swarm::{NetworkBehaviour, THandlerInEvent, ToSwarm},
-    Multiaddr, PeerId, StreamProtocol,
+    futures::FutureExt as _,
+    request_response::ProtocolSupport,
+    swarm::{behaviour::ConnectionClosed, FromSwarm, NetworkBehaviour, THandlerInEvent, ToSwarm},
+    Multiaddr, PeerId,
 };
+use uuid::Uuid;
 
 use super::{
-    message::{RendezvousCommand, RendezvousRequest, RendezvousResponse},
+    message::{
+        DiscoverPage, Protocols, ProtocolsEvent, RegistrationLease, RendezvousCommand,
+        RendezvousRequest, RendezvousResponse, WatchNotification, WatchRequest,
+    },
+    query::Query,
     registrations::{Registration, Registrations},
 };
 
@@ -19,14 +28,48 @@ use super::{
 pub struct Config {
     database: String,
 
+    /// Shortest TTL granted to registrations, whatever the client asks for
+    #[builder(default = "chrono::TimeDelta::minutes(1)")]
+    min_lifetime: TimeDelta,
+
+    /// Longest TTL granted to registrations, and the TTL of those that don't ask for one
     #[builder(default = "chrono::TimeDelta::hours(12)")]
     max_lifetime: TimeDelta,
+
+    /// Most registrations returned in a single discovery response, whatever the client asks for
+    #[builder(default = "100")]
+    max_page_size: u32,
 }
 
 pub struct Behavior {
-    inner: libp2p::request_response::cbor::Behaviour<RendezvousRequest, RendezvousResponse>,
+    inner: Protocols,
     config: Config,
     registrations: Registrations,
+    watches: HashMap<Uuid, Watch>,
+    expiry_timer: Option<(DateTime<Utc>, futures_timer::Delay)>,
+}
+
+/// A subscription of a connected peer to the registrations it could discover
+#[derive(Clone, Debug)]
+struct Watch {
+    peer: PeerId,
+    source: NodeIdentifier,
+    group: Option<String>,
+    filter: Option<Query>,
+}
+
+impl Watch {
+    fn covers(&self, registration: &Registration) -> bool {
+        registration.discoverable_by(&self.source)
+            && self
+                .group
+                .as_ref()
+                .is_none_or(|group| &registration.identity.group() == group)
+            && self
+                .filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(&registration.identity))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -64,13 +107,34 @@ pub enum Event {
         source: NodeIdentifier,
         error: InterplexError,
     },
+
+    Watching {
+        source: NodeIdentifier,
+        watch: Uuid,
+    },
+    FailedWatch {
+        source: NodeIdentifier,
+        error: InterplexError,
+    },
+    Unwatched {
+        source: NodeIdentifier,
+        watch: Uuid,
+    },
+    FailedUnwatch {
+        source: NodeIdentifier,
+        error: InterplexError,
+    },
+
+    /// A peer sent a request in the name of another peer, which was rejected
+    SpoofedRequest {
+        peer: PeerId,
+        source: NodeIdentifier,
+        command: RendezvousCommand,
+    },
 }
 
 impl NetworkBehaviour for Behavior {
-    type ConnectionHandler = <libp2p::request_response::cbor::Behaviour<
-        RendezvousRequest,
-        RendezvousResponse,
-    > as NetworkBehaviour>::ConnectionHandler;
+    type ConnectionHandler = <Protocols as NetworkBehaviour>::ConnectionHandler;
 
     type ToSwarm = Event;
 
@@ -107,6 +171,16 @@ impl NetworkBehaviour for Behavior {
     }
 
     fn on_swarm_event(&mut self, event: libp2p::swarm::FromSwarm) {
+        // Notifications can't reach a disconnected peer, which has to watch again on reconnection
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            remaining_established: 0,
+            ..
+        }) = event
+        {
+            self.forget_watches(&peer_id);
+        }
+
         self.inner.on_swarm_event(event);
     }
 
@@ -124,23 +198,26 @@ impl NetworkBehaviour for Behavior {
         &mut self,
         cx: &mut Context<'_>,
     ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
-        if let Ok(Some(expired)) = self.registrations.poll(self.config.max_lifetime) {
-            return Poll::Ready(ToSwarm::GenerateEvent(Event::ExpiredRegistration(expired)));
-        }
         loop {
+            if let Ok(Some(expired)) = self.registrations.poll() {
+                self.notify_watches(Some(&expired), None, true);
+                return Poll::Ready(ToSwarm::GenerateEvent(Event::ExpiredRegistration(expired)));
+            }
+
             if let Poll::Ready(to_swarm) = self.inner.poll(cx) {
                 match to_swarm {
-                    ToSwarm::GenerateEvent(libp2p::request_response::Event::Message {
+                    ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(libp2p::request_response::Event::Message {
                         peer: peer_id,
                         message:
                             libp2p::request_response::Message::Request {
                                 request, channel, ..
                             },
                         ..
-                    }) => {
+                    })) => {
                         if let Some((event, response)) = self.handle_request(peer_id, request) {
                             if let Some(resp) = response {
                                 self.inner
+                                    .rendezvous
                                     .send_response(channel, resp)
                                     .expect("Send response");
                             }
@@ -150,12 +227,12 @@ impl NetworkBehaviour for Behavior {
 
                         continue;
                     }
-                    ToSwarm::GenerateEvent(libp2p::request_response::Event::InboundFailure {
+                    ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(libp2p::request_response::Event::InboundFailure {
                         peer,
                         request_id,
                         error,
                         ..
-                    }) => {
+                    })) => {
                         tracing::warn!(
                             %peer,
                             request=%request_id,
@@ -164,17 +241,29 @@ impl NetworkBehaviour for Behavior {
 
                         continue;
                     }
-                    ToSwarm::GenerateEvent(libp2p::request_response::Event::ResponseSent {
+                    ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(libp2p::request_response::Event::ResponseSent {
                         ..
-                    })
-                    | ToSwarm::GenerateEvent(libp2p::request_response::Event::Message {
+                    }))
+                    | ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(libp2p::request_response::Event::Message {
                         peer: _,
                         message: libp2p::request_response::Message::Response { .. },
                         ..
-                    })
-                    | ToSwarm::GenerateEvent(libp2p::request_response::Event::OutboundFailure {
+                    }))
+                    | ToSwarm::GenerateEvent(ProtocolsEvent::Rendezvous(libp2p::request_response::Event::OutboundFailure {
                         ..
-                    }) => {
+                    })) => {
+                        continue;
+                    }
+                    ToSwarm::GenerateEvent(ProtocolsEvent::Watch(libp2p::request_response::Event::OutboundFailure {
+                        peer,
+                        error,
+                        ..
+                    })) => {
+                        tracing::debug!(%peer, "Failed to notify watching peer: {error}");
+
+                        continue;
+                    }
+                    ToSwarm::GenerateEvent(ProtocolsEvent::Watch(_)) => {
                         continue;
                     }
                     other => {
@@ -186,6 +275,21 @@ impl NetworkBehaviour for Behavior {
                 };
             }
 
+            // Nothing else wakes the behaviour when registrations expire
+            if let Ok(Some(next)) = self.registrations.next_expiration() {
+                if self.expiry_timer.as_ref().is_none_or(|(at, _)| at != &next) {
+                    let delay = (next - Utc::now()).to_std().unwrap_or_default();
+                    self.expiry_timer = Some((next, futures_timer::Delay::new(delay)));
+                }
+
+                if let Some((_, timer)) = self.expiry_timer.as_mut() {
+                    if timer.poll_unpin(cx).is_ready() {
+                        self.expiry_timer = None;
+                        continue;
+                    }
+                }
+            }
+
             return Poll::Pending;
         }
     }
@@ -194,35 +298,114 @@ impl NetworkBehaviour for Behavior {
 impl Behavior {
     pub fn new(config: Config) -> Self {
         Self {
-            inner: libp2p::request_response::cbor::Behaviour::new(
-                [(
-                    StreamProtocol::new("/interplex/rendezvous"),
-                    ProtocolSupport::Full,
-                )],
-                request_response::Config::default(),
-            ),
+            inner: Protocols::new(ProtocolSupport::Inbound, ProtocolSupport::Outbound),
             config: config.clone(),
             registrations: Registrations::new(config.database),
+            watches: Default::default(),
+            expiry_timer: None,
         }
     }
 
-    pub fn handle_request(
+    /// Notifies every watch covering a registration that changed from `before` to `after`
+    fn notify_watches(
+        &mut self,
+        before: Option<&Registration>,
+        after: Option<&Registration>,
+        expired: bool,
+    ) {
+        for (peer, request) in self.notifications(before, after, expired) {
+            self.inner.watch.send_request(&peer, request);
+        }
+    }
+
+    /// Notifications of the watches covering a registration that changed from `before` to
+    /// `after`, along with the peer each is sent to
+    fn notifications(
         &self,
-        _: PeerId,
+        before: Option<&Registration>,
+        after: Option<&Registration>,
+        expired: bool,
+    ) -> Vec<(PeerId, WatchRequest)> {
+        let mut notifications = Vec::new();
+        for (id, watch) in &self.watches {
+            let covered_before = before.filter(|registration| watch.covers(registration));
+            let covered_after = after.filter(|registration| watch.covers(registration));
+            let notification = match (covered_before, covered_after) {
+                (None, Some(registration)) => WatchNotification::Joined(registration.clone()),
+                (Some(_), Some(registration)) => WatchNotification::Updated(registration.clone()),
+                (Some(registration), None) if expired => {
+                    WatchNotification::Expired(registration.clone())
+                }
+                (Some(registration), None) => WatchNotification::Left(registration.clone()),
+                (None, None) => continue,
+            };
+
+            notifications.push((
+                watch.peer,
+                WatchRequest {
+                    watch: *id,
+                    notification,
+                },
+            ));
+        }
+
+        notifications
+    }
+
+    /// Drops the watches of a peer, which notifications can no longer reach
+    fn forget_watches(&mut self, peer: &PeerId) {
+        self.watches.retain(|_, watch| &watch.peer != peer);
+    }
+
+    pub fn handle_request(
+        &mut self,
+        peer: PeerId,
         request: RendezvousRequest,
     ) -> Option<(Event, Option<RendezvousResponse>)> {
+        // The connection's peer ID is authenticated, while the source is whatever the peer claims
+        if request.source.peer_id != peer {
+            let error = InterplexError::SourceMismatch {
+                claimed: request.source.peer_id,
+                actual: peer,
+            };
+            return Some((
+                Event::SpoofedRequest {
+                    peer,
+                    source: request.source,
+                    command: request.command.clone(),
+                },
+                Some(RendezvousResponse::failure(&request.command, error)),
+            ));
+        }
+
         match request.command.clone() {
-            RendezvousCommand::Register(addresses) => {
-                match self
-                    .registrations
-                    .register(request.source.clone(), addresses, self.config.max_lifetime)
-                {
-                    Ok(reg) => Some((
-                        Event::CreatedRegistration(reg.clone()),
-                        Some(RendezvousResponse::Register(Ok(
-                            reg.last_registration + self.config.max_lifetime
-                        ))),
-                    )),
+            RendezvousCommand::Register { record, ttl } => {
+                // Clamped by hand, as clamp panics if the configured range is empty
+                let ttl = ttl
+                    .unwrap_or(self.config.max_lifetime)
+                    .max(self.config.min_lifetime)
+                    .min(self.config.max_lifetime);
+                match record.verify().and_then(|verified| {
+                    // The record must describe the peer that sent it, which is checked above
+                    if verified.identity.peer_id != peer {
+                        return Err(InterplexError::InvalidRecord(format!(
+                            "Record of {} was sent by {peer}",
+                            verified.identity.peer_id
+                        )));
+                    }
+
+                    self.registrations.register(verified, record, ttl)
+                }) {
+                    Ok((reg, previous)) => {
+                        self.notify_watches(previous.as_ref(), Some(&reg), false);
+                        Some((
+                            Event::CreatedRegistration(reg.clone()),
+                            Some(RendezvousResponse::Register(Ok(RegistrationLease {
+                                ttl: reg.ttl,
+                                expiration: reg.expiration(),
+                            }))),
+                        ))
+                    }
                     Err(e) => Some((
                         Event::RegistrationFailure(request.source.clone(), e.clone()),
                         Some(RendezvousResponse::Register(Err(e.clone()))),
@@ -231,29 +414,41 @@ impl Behavior {
             }
             RendezvousCommand::Deregister => {
                 match self.registrations.deregister(request.source.clone()) {
-                    Ok(()) => Some((
-                        Event::RemovedRegistration(request.source.clone()),
-                        Some(RendezvousResponse::Deregister(Ok(()))),
-                    )),
+                    Ok(removed) => {
+                        self.notify_watches(removed.as_ref(), None, false);
+                        Some((
+                            Event::RemovedRegistration(request.source.clone()),
+                            Some(RendezvousResponse::Deregister(Ok(()))),
+                        ))
+                    }
                     Err(e) => Some((
                         Event::DeregistrationFailure(request.source.clone(), e.clone()),
                         Some(RendezvousResponse::Deregister(Err(e.clone()))),
                     )),
                 }
             }
-            RendezvousCommand::Discover(group) => {
-                match self
-                    .registrations
-                    .discover(request.source.clone(), group.clone())
-                {
-                    Ok(results) => Some((
+            RendezvousCommand::Discover { group, query, limit, cursor } => {
+                let limit = limit
+                    .unwrap_or(self.config.max_page_size)
+                    .clamp(1, self.config.max_page_size.max(1));
+                match self.registrations.discover(
+                    request.source.clone(),
+                    group.clone(),
+                    query.as_ref(),
+                    limit as usize,
+                    cursor,
+                ) {
+                    Ok((registrations, next_cursor)) => Some((
                         Event::ServedDiscovery {
                             source: request.source.clone(),
                             namespace: request.source.namespace.clone(),
                             group: group.clone(),
-                            results: results.len() as u64,
+                            results: registrations.len() as u64,
                         },
-                        Some(RendezvousResponse::Discover(Ok(results))),
+                        Some(RendezvousResponse::Discover(Ok(DiscoverPage {
+                            registrations,
+                            next_cursor,
+                        }))),
                     )),
                     Err(e) => Some((
                         Event::FailedDiscovery {
@@ -266,7 +461,7 @@ impl Behavior {
                     )),
                 }
             }
-            RendezvousCommand::Find(key) => match self.registrations.get(key.clone()) {
+            RendezvousCommand::Find(key) => match self.registrations.get(&request.source, key.clone()) {
                 Ok(result) => Some((
                     Event::ServedFind {
                         source: request.source.clone(),
@@ -283,7 +478,7 @@ impl Behavior {
                 )),
             },
             RendezvousCommand::Groups => {
-                match self.registrations.groups(request.source.namespace.clone()) {
+                match self.registrations.groups(&request.source) {
                     Ok(result) => Some((
                         Event::ServedGroups {
                             source: request.source.clone(),
@@ -300,6 +495,371 @@ impl Behavior {
                     )),
                 }
             }
+            RendezvousCommand::Watch { watch, group, filter } => {
+                // IDs are chosen by clients, so they may not replace the watches of others
+                let covered = if self.watches.contains_key(&watch) {
+                    Err(InterplexError::WatchExists(watch))
+                } else {
+                    // Only the first page is sent, the rest is discovered with the returned cursor
+                    self.registrations.discover(
+                        request.source.clone(),
+                        group.clone(),
+                        filter.as_ref(),
+                        self.config.max_page_size.max(1) as usize,
+                        None,
+                    )
+                };
+
+                match covered {
+                    Ok((registrations, next_cursor)) => {
+                        self.watches.insert(
+                            watch,
+                            Watch {
+                                peer,
+                                source: request.source.clone(),
+                                group,
+                                filter,
+                            },
+                        );
+                        Some((
+                            Event::Watching {
+                                source: request.source.clone(),
+                                watch,
+                            },
+                            Some(RendezvousResponse::Watch(Ok(DiscoverPage {
+                                registrations,
+                                next_cursor,
+                            }))),
+                        ))
+                    }
+                    Err(e) => Some((
+                        Event::FailedWatch {
+                            source: request.source.clone(),
+                            error: e.clone(),
+                        },
+                        Some(RendezvousResponse::Watch(Err(e))),
+                    )),
+                }
+            }
+            RendezvousCommand::Unwatch(watch) => {
+                // Peers may only cancel their own watches
+                match self.watches.get(&watch) {
+                    Some(existing) if existing.peer == peer => {
+                        self.watches.remove(&watch);
+                        Some((
+                            Event::Unwatched {
+                                source: request.source.clone(),
+                                watch,
+                            },
+                            Some(RendezvousResponse::Unwatch(Ok(()))),
+                        ))
+                    }
+                    _ => {
+                        let e = InterplexError::not_found(watch.to_string());
+                        Some((
+                            Event::FailedUnwatch {
+                                source: request.source.clone(),
+                                error: e.clone(),
+                            },
+                            Some(RendezvousResponse::Unwatch(Err(e))),
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::{
+        identification::{Discoverability, NodeBuilder},
+        rendezvous::record::{PeerRecord, SignedPeerRecord},
+    };
+
+    /// Runs a server on a fresh temporary database, removed when the test ends
+    struct TempServer(Behavior, std::path::PathBuf);
+
+    impl TempServer {
+        fn new(config: &mut ConfigBuilder) -> Self {
+            let path = std::env::temp_dir().join(format!("interplex-server-{}", Uuid::new_v4()));
+            let config = config.database(path.to_string_lossy()).build().unwrap();
+            Self(Behavior::new(config), path)
+        }
+    }
+
+    impl Drop for TempServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.1);
+        }
+    }
+
+    fn identity(keypair: &Keypair) -> NodeIdentifier {
+        NodeBuilder::new_from_id("test", keypair.public().to_peer_id())
+            .discoverability(Discoverability::Namespace)
+            .build()
+            .unwrap()
+    }
+
+    /// A register request of `identity`, signed by `keypair`
+    fn register(
+        keypair: &Keypair,
+        identity: NodeIdentifier,
+        ttl: Option<TimeDelta>,
+    ) -> RendezvousRequest {
+        let record = SignedPeerRecord::new(
+            keypair,
+            &PeerRecord {
+                identity: identity.clone(),
+                addresses: vec!["/ip4/127.0.0.1/tcp/4001".parse().unwrap()],
+                timestamp: Utc::now(),
+            },
+        )
+        .unwrap();
+        RendezvousRequest {
+            source: identity,
+            command: RendezvousCommand::Register { record, ttl },
         }
     }
+
+    #[test]
+    fn rejects_requests_with_spoofed_source() {
+        let mut server = TempServer::new(&mut ConfigBuilder::default());
+        let victim = Keypair::generate_ed25519();
+        let spoofer = Keypair::generate_ed25519().public().to_peer_id();
+        let source = identity(&victim);
+
+        let (event, response) = server
+            .0
+            .handle_request(spoofer, register(&victim, source.clone(), None))
+            .unwrap();
+
+        assert!(matches!(
+            event,
+            Event::SpoofedRequest {
+                peer,
+                source: claimed,
+                command: RendezvousCommand::Register { .. },
+            } if peer == spoofer && claimed.peer_id == source.peer_id
+        ));
+        assert!(matches!(
+            response,
+            Some(RendezvousResponse::Register(Err(InterplexError::SourceMismatch {
+                claimed,
+                actual,
+            }))) if claimed == source.peer_id && actual == spoofer
+        ));
+        assert!(server.0.registrations.get(&source, source.key()).unwrap().is_none());
+    }
+
+    #[test]
+    fn clamps_discovery_limit_to_page_size() {
+        let mut server = TempServer::new(ConfigBuilder::default().max_page_size(2u32));
+        for _ in 0..3 {
+            let keypair = Keypair::generate_ed25519();
+            let request = register(&keypair, identity(&keypair), None);
+            server.0.handle_request(keypair.public().to_peer_id(), request).unwrap();
+        }
+
+        let requester = identity(&Keypair::generate_ed25519());
+        for (limit, expected) in [(Some(0), 1), (Some(1), 1), (Some(100), 2), (None, 2)] {
+            let request = RendezvousRequest {
+                source: requester.clone(),
+                command: RendezvousCommand::Discover {
+                    group: None,
+                    query: None,
+                    limit,
+                    cursor: None,
+                },
+            };
+            let (_, response) = server.0.handle_request(requester.peer_id, request).unwrap();
+            let Some(RendezvousResponse::Discover(Ok(page))) = response else {
+                panic!("unexpected response {response:?}");
+            };
+
+            assert_eq!(page.registrations.len(), expected, "limit {limit:?}");
+            assert!(page.next_cursor.is_some(), "limit {limit:?}");
+        }
+    }
+
+    #[test]
+    fn clamps_requested_ttl() {
+        let mut server = TempServer::new(
+            ConfigBuilder::default()
+                .min_lifetime(TimeDelta::minutes(1))
+                .max_lifetime(TimeDelta::hours(1)),
+        );
+        let keypair = Keypair::generate_ed25519();
+        let peer = keypair.public().to_peer_id();
+
+        for (requested, granted) in [
+            (Some(TimeDelta::seconds(1)), TimeDelta::minutes(1)),
+            (Some(TimeDelta::minutes(10)), TimeDelta::minutes(10)),
+            (Some(TimeDelta::hours(2)), TimeDelta::hours(1)),
+            (None, TimeDelta::hours(1)),
+        ] {
+            let before = Utc::now();
+            let request = register(&keypair, identity(&keypair), requested);
+            let (_, response) = server.0.handle_request(peer, request).unwrap();
+            let Some(RendezvousResponse::Register(Ok(lease))) = response else {
+                panic!("unexpected response {response:?}");
+            };
+
+            assert_eq!(lease.ttl, granted, "requested {requested:?}");
+            assert!(lease.expiration >= before + granted, "requested {requested:?}");
+            assert!(lease.expiration <= Utc::now() + granted, "requested {requested:?}");
+        }
+    }
+
+    /// Creates a watch of `peer` over the namespace of the test identities
+    fn watch(server: &mut Behavior, peer: &Keypair, watch: Uuid, group: Option<&str>) -> Event {
+        let source = identity(peer);
+        let request = RendezvousRequest {
+            source: source.clone(),
+            command: RendezvousCommand::Watch {
+                watch,
+                group: group.map(String::from),
+                filter: None,
+            },
+        };
+        server.handle_request(source.peer_id, request).unwrap().0
+    }
+
+    /// Registers `identity` in the server's store, returning the registration and the one it
+    /// replaced
+    fn store(
+        server: &Behavior,
+        keypair: &Keypair,
+        identity: NodeIdentifier,
+    ) -> (Registration, Option<Registration>) {
+        let RendezvousRequest {
+            command: RendezvousCommand::Register { record, .. },
+            ..
+        } = register(keypair, identity, None)
+        else {
+            unreachable!()
+        };
+        server
+            .registrations
+            .register(record.verify().unwrap(), record, TimeDelta::minutes(10))
+            .unwrap()
+    }
+
+    /// Kinds of the notifications sent for a change, with the watch and peer they are sent to
+    fn notified(
+        server: &Behavior,
+        before: Option<&Registration>,
+        after: Option<&Registration>,
+        expired: bool,
+    ) -> Vec<(PeerId, Uuid, &'static str)> {
+        server
+            .notifications(before, after, expired)
+            .into_iter()
+            .map(|(peer, request)| {
+                let kind = match request.notification {
+                    WatchNotification::Joined(_) => "joined",
+                    WatchNotification::Updated(_) => "updated",
+                    WatchNotification::Left(_) => "left",
+                    WatchNotification::Expired(_) => "expired",
+                };
+                (peer, request.watch, kind)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn notifies_watches_of_changes() {
+        let mut server = TempServer::new(&mut ConfigBuilder::default());
+        let watcher = Keypair::generate_ed25519();
+        let watcher_id = watcher.public().to_peer_id();
+        let (everyone, ops) = (Uuid::new_v4(), Uuid::new_v4());
+        watch(&mut server.0, &watcher, everyone, None);
+        watch(&mut server.0, &watcher, ops, Some("ops"));
+
+        let keypair = Keypair::generate_ed25519();
+        let (joined, previous) = store(&server.0, &keypair, identity(&keypair));
+        assert_eq!(
+            notified(&server.0, previous.as_ref(), Some(&joined), false),
+            [(watcher_id, everyone, "joined")]
+        );
+
+        let mut renamed = identity(&keypair);
+        renamed.alias = Some(String::from("renamed"));
+        let (updated, previous) = store(&server.0, &keypair, renamed);
+        assert_eq!(
+            notified(&server.0, previous.as_ref(), Some(&updated), false),
+            [(watcher_id, everyone, "updated")]
+        );
+
+        // Changing to a mode that hides it from discoveries leaves the watch
+        let mut hidden = identity(&keypair);
+        hidden.discoverability = Discoverability::Direct;
+        let (unlisted, previous) = store(&server.0, &keypair, hidden);
+        assert_eq!(
+            notified(&server.0, previous.as_ref(), Some(&unlisted), false),
+            [(watcher_id, everyone, "left")]
+        );
+        assert!(notified(&server.0, Some(&unlisted), None, false).is_empty());
+
+        assert_eq!(
+            notified(&server.0, Some(&updated), None, false),
+            [(watcher_id, everyone, "left")]
+        );
+        assert_eq!(
+            notified(&server.0, Some(&updated), None, true),
+            [(watcher_id, everyone, "expired")]
+        );
+
+        // Registrations outside the namespace of the watcher aren't covered
+        let stranger = Keypair::generate_ed25519();
+        let elsewhere = NodeBuilder::new_from_id("other", stranger.public().to_peer_id())
+            .discoverability(Discoverability::Namespace)
+            .build()
+            .unwrap();
+        let (elsewhere, _) = store(&server.0, &stranger, elsewhere);
+        assert!(notified(&server.0, None, Some(&elsewhere), false).is_empty());
+    }
+
+    #[test]
+    fn rejects_duplicate_watch_ids() {
+        let mut server = TempServer::new(&mut ConfigBuilder::default());
+        let (first, second) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let id = Uuid::new_v4();
+
+        assert!(matches!(
+            watch(&mut server.0, &first, id, None),
+            Event::Watching { watch, .. } if watch == id
+        ));
+        for peer in [&first, &second] {
+            assert!(matches!(
+                watch(&mut server.0, peer, id, None),
+                Event::FailedWatch { error: InterplexError::WatchExists(watch), .. } if watch == id
+            ));
+        }
+        assert_eq!(server.0.watches[&id].peer, first.public().to_peer_id());
+    }
+
+    #[test]
+    fn forgets_watches_of_disconnected_peers() {
+        let mut server = TempServer::new(&mut ConfigBuilder::default());
+        let (gone, staying) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+        let kept = Uuid::new_v4();
+        watch(&mut server.0, &gone, Uuid::new_v4(), None);
+        watch(&mut server.0, &gone, Uuid::new_v4(), Some("ops"));
+        watch(&mut server.0, &staying, kept, None);
+
+        server.0.forget_watches(&gone.public().to_peer_id());
+        assert_eq!(server.0.watches.keys().collect::<Vec<_>>(), [&kept]);
+
+        // Forgotten watches no longer receive notifications
+        let keypair = Keypair::generate_ed25519();
+        let (joined, _) = store(&server.0, &keypair, identity(&keypair));
+        assert_eq!(
+            notified(&server.0, None, Some(&joined), false),
+            [(staying.public().to_peer_id(), kept, "joined")]
+        );
+    }
 }